@@ -0,0 +1,216 @@
+// Benchmark input generation does plain index/counter arithmetic.
+#![allow(
+    clippy::arithmetic_side_effects,
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+
+use std::{fmt::Write, hint::black_box, io};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use payments::{
+    process_csv, process_operation, read_operations, ClientDb, ClientId, Operation,
+    OperationReader, OperationType, TransactionDb, TransactionId,
+};
+use rust_decimal::Decimal;
+
+const CLIENTS: u16 = 1000;
+
+/// Small deterministic PRNG (xorshift64), so generated inputs are identical between runs.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, ratio: f64) -> bool {
+        const SCALE: u64 = 1 << 20;
+        self.below(SCALE) < (ratio * SCALE as f64) as u64
+    }
+}
+
+/// Generates CSV input with `rows` operations. Roughly `dispute_ratio` of rows are
+/// disputes/resolves/chargebacks, the rest are deposits and withdrawals. Withdrawals (almost)
+/// never exceed the available balance, so rows are accepted and nothing is logged to stderr.
+fn generate_input(rows: usize, dispute_ratio: f64) -> String {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    // balances in 1/10000 units
+    let mut balances = vec![0_u64; usize::from(CLIENTS) + 1];
+    let mut deposits: Vec<(u16, u32, u64)> = Vec::new();
+    let mut disputes: Vec<(u16, u32, u64)> = Vec::new();
+    let mut next_tx = 1_u32;
+
+    let mut input = String::with_capacity(rows * 32);
+    input.push_str("type, client, tx, amount\n");
+    for _ in 0..rows {
+        if rng.chance(dispute_ratio) {
+            if !disputes.is_empty() && rng.chance(0.5) {
+                let idx = rng.below(disputes.len() as u64) as usize;
+                let (client, tx, amount) = disputes.swap_remove(idx);
+                let op = if rng.chance(0.9) {
+                    balances[usize::from(client)] += amount;
+                    "resolve"
+                } else {
+                    "chargeback"
+                };
+                writeln!(input, "{op}, {client}, {tx},").unwrap();
+                continue;
+            }
+            if !deposits.is_empty() {
+                let idx = rng.below(deposits.len() as u64) as usize;
+                let (client, tx, amount) = deposits.swap_remove(idx);
+                let balance = &mut balances[usize::from(client)];
+                // held funds can't be withdrawn (and the dispute may leave available negative)
+                *balance = balance.saturating_sub(amount);
+                disputes.push((client, tx, amount));
+                writeln!(input, "dispute, {client}, {tx},").unwrap();
+                continue;
+            }
+        }
+
+        let client = 1 + rng.below(u64::from(CLIENTS)) as u16;
+        let tx = next_tx;
+        next_tx += 1;
+        let amount = 1 + rng.below(1_000_000);
+        let balance = &mut balances[usize::from(client)];
+        if rng.chance(0.3) && *balance >= amount {
+            *balance -= amount;
+            writeln!(
+                input,
+                "withdrawal, {client}, {tx}, {}",
+                Decimal::new(amount as i64, 4)
+            )
+            .unwrap();
+        } else {
+            *balance += amount;
+            deposits.push((client, tx, amount));
+            writeln!(
+                input,
+                "deposit, {client}, {tx}, {}",
+                Decimal::new(amount as i64, 4)
+            )
+            .unwrap();
+        }
+    }
+    input
+}
+
+fn operation(op_type: OperationType, tx: u64, amount: Option<Decimal>) -> Operation {
+    Operation {
+        op_type,
+        client: ClientId(1),
+        tx: TransactionId(tx),
+        amount,
+        timestamp: None,
+        to: None,
+        reason: None,
+        source: None,
+    }
+}
+
+fn bench_parsing(c: &mut Criterion) {
+    const ROWS: usize = 100_000;
+    let input = generate_input(ROWS, 0.05);
+
+    let mut group = c.benchmark_group("parsing");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("read_operations", |b| {
+        b.iter(|| {
+            for operation in read_operations(input.as_bytes()) {
+                black_box(operation.unwrap());
+            }
+        })
+    });
+    group.bench_function("operation_reader", |b| {
+        b.iter(|| {
+            for operation in OperationReader::new(input.as_bytes()) {
+                black_box(operation.unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_process_operation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_operation");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("deposit", |b| {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+        let mut tx = 0;
+        b.iter(|| {
+            tx += 1;
+            let deposit = operation(OperationType::Deposit, tx, Some(Decimal::ONE));
+            process_operation(&mut clients, &mut transactions, black_box(&deposit)).unwrap();
+        })
+    });
+
+    group.bench_function("withdrawal", |b| {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+        let funding = operation(OperationType::Deposit, 0, Some(Decimal::from(u32::MAX)));
+        process_operation(&mut clients, &mut transactions, &funding).unwrap();
+        let mut tx = 0;
+        b.iter(|| {
+            tx += 1;
+            let withdrawal = operation(OperationType::Withdrawal, tx, Some(Decimal::ONE));
+            process_operation(&mut clients, &mut transactions, black_box(&withdrawal)).unwrap();
+        })
+    });
+
+    group.bench_function("dispute_resolve", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut clients = ClientDb::default();
+                let mut transactions = TransactionDb::default();
+                let deposit = operation(OperationType::Deposit, 1, Some(Decimal::ONE));
+                process_operation(&mut clients, &mut transactions, &deposit).unwrap();
+                (clients, transactions)
+            },
+            |(clients, transactions)| {
+                let dispute = operation(OperationType::Dispute, 1, None);
+                process_operation(clients, transactions, black_box(&dispute)).unwrap();
+                let resolve = operation(OperationType::Resolve, 1, None);
+                process_operation(clients, transactions, black_box(&resolve)).unwrap();
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn bench_end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_csv");
+    group.sample_size(10);
+
+    for rows in [1_000_000, 10_000_000] {
+        for dispute_ratio in [0.0, 0.01, 0.1] {
+            let input = generate_input(rows, dispute_ratio);
+            group.throughput(Throughput::Elements(rows as u64));
+            group.bench_function(format!("rows={rows}/disputes={dispute_ratio}"), |b| {
+                b.iter(|| process_csv(input.as_bytes(), io::sink()).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parsing,
+    bench_process_operation,
+    bench_end_to_end
+);
+criterion_main!(benches);
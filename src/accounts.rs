@@ -0,0 +1,40 @@
+use std::io;
+
+use rust_decimal::Decimal;
+
+use crate::{AccountStatus, ClientId};
+
+/// Account from an accounts file, opened before processing. All columns but `client` are
+/// optional, so balances output of an earlier run can be used as is (extra columns, e.g. `fees`,
+/// are ignored).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct OpeningAccount {
+    pub client: ClientId,
+    #[serde(default)]
+    pub available: Decimal,
+    #[serde(default)]
+    pub held: Decimal,
+    /// Withdrawals on hold and authorizations of an earlier run, kept reserved (like held funds).
+    #[serde(default)]
+    pub reserved: Decimal,
+    /// Checked against `available + held + reserved`.
+    #[serde(default)]
+    pub total: Option<Decimal>,
+    #[serde(default)]
+    pub locked: bool,
+    /// `open` (default), `pending`, `frozen` or `closed`.
+    #[serde(default)]
+    pub status: Option<AccountStatus>,
+    /// Reason of a lock or closure.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Reads accounts file (CSV with headers, whitespace around fields is ignored).
+pub fn read_accounts<R: io::Read>(reader: R) -> impl Iterator<Item = csv::Result<OpeningAccount>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .has_headers(true)
+        .from_reader(reader)
+        .into_deserialize()
+}
@@ -1,17 +1,42 @@
 use anyhow::{ensure, Context};
 use indexmap::IndexMap;
 use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
 
-use crate::transaction::{
-    Chargeback, Deposit, Dispute, PersistedTx, Resolve, TransactionId, Withdrawal,
-};
+use crate::{event::Event, limits::Limits, transaction::TransactionId};
 
-#[derive(Default, Debug)]
-pub(crate) struct AccountState {
+/// Lifecycle state of an account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    /// Known, but not opened yet (operations are rejected until it's opened).
+    Pending,
+    #[default]
+    Open,
+    /// Locked by a chargeback or manually.
+    Frozen,
+    /// Closed for good (operations are rejected).
+    Closed,
+}
+
+impl AccountStatus {
+    pub fn is_open(&self) -> bool {
+        *self == Self::Open
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct AccountState {
     available: Decimal,
     held: Decimal,
+    /// Withdrawals on hold (awaiting review) and authorizations (awaiting capture).
+    reserved: Decimal,
     total: Decimal,
-    locked: bool,
+    status: AccountStatus,
+    /// Reason of the last administrative change (lock, unlock, close).
+    reason: Option<String>,
+    /// Fees charged so far.
+    fees: Decimal,
 }
 
 pub(crate) struct AuthorizedWithdrawal {
@@ -30,14 +55,98 @@ impl AuthorizedWithdrawal {
 }
 
 impl AccountState {
-    pub(crate) fn deposit(&mut self, deposit: PersistedTx<Deposit>) -> anyhow::Result<()> {
+    /// Updates account of `client_id` with an event (for this client or its counterparty).
+    pub(crate) fn apply(&mut self, client_id: ClientId, event: &Event) -> anyhow::Result<()> {
+        if event.counterparty() == Some(client_id) {
+            return match *event {
+                Event::Transferred { amount, .. } | Event::ChargedBack { amount, .. } => {
+                    self.deposit(amount)
+                }
+                Event::ChargebackReversed { amount, .. } => self.withdraw(amount),
+                _ => unreachable!("event has no counterparty"),
+            };
+        }
+        match *event {
+            Event::Deposited { amount, .. } => self.deposit(amount),
+            Event::Withdrawn { amount, .. } => self.withdraw(amount),
+            Event::DisputeOpened { amount, .. } => self.dispute_deposit(amount),
+            Event::DisputeResolved { amount, .. } => self.resolve_dispute(amount),
+            Event::ChargedBack { amount, .. } => self.chargeback(amount),
+            Event::AccountOpened {
+                status,
+                available,
+                held,
+                reserved,
+                ..
+            } => {
+                self.open(available, held, reserved)?;
+                self.status = status;
+                Ok(())
+            }
+            Event::AccountLocked { ref reason, .. } => {
+                self.status = AccountStatus::Frozen;
+                self.reason.clone_from(reason);
+                Ok(())
+            }
+            Event::ChargebackReversed { amount, .. } => self.deposit(amount),
+            Event::AccountUnlocked { ref reason, .. } => {
+                self.status = AccountStatus::Open;
+                self.reason.clone_from(reason);
+                Ok(())
+            }
+            Event::AccountClosed { ref reason, .. } => {
+                self.status = AccountStatus::Closed;
+                self.reason.clone_from(reason);
+                Ok(())
+            }
+            Event::WithdrawalHeld { amount, .. } => self.reserve(amount),
+            Event::WithdrawalApproved { amount, .. } => self.withdraw_reserved(amount),
+            Event::WithdrawalDeclined { amount, .. } => self.release_reserved(amount),
+            Event::Authorized { amount, .. } => self.reserve(amount),
+            Event::Captured {
+                amount, released, ..
+            } => self.capture(amount, released),
+            Event::Voided { amount, .. } => self.release_reserved(amount),
+            Event::Transferred { amount, .. } => self.withdraw(amount),
+            Event::FeeCharged { amount, .. } => self.charge_fee(amount),
+            Event::TransactionScoped { .. } => Ok(()),
+        }
+    }
+
+    /// Adds opening balances.
+    fn open(&mut self, available: Decimal, held: Decimal, reserved: Decimal) -> anyhow::Result<()> {
+        let new_available = self
+            .available
+            .checked_add(available)
+            .context("available amount overflow")?;
+        let new_held = self
+            .held
+            .checked_add(held)
+            .context("held amount overflow")?;
+        let new_reserved = self
+            .reserved
+            .checked_add(reserved)
+            .context("reserved amount overflow")?;
+        let new_total = new_available
+            .checked_add(new_held)
+            .and_then(|total| total.checked_add(new_reserved))
+            .context("total amount overflow")?;
+
+        self.available = new_available;
+        self.held = new_held;
+        self.reserved = new_reserved;
+        self.total = new_total;
+        Ok(())
+    }
+
+    fn deposit(&mut self, amount: Decimal) -> anyhow::Result<()> {
         let new_available = self
             .available
-            .checked_add(deposit.amount())
+            .checked_add(amount)
             .context("available amount overflow")?;
         let new_total = self
             .total
-            .checked_add(deposit.amount())
+            .checked_add(amount)
             .context("total amount overflow")?;
 
         // note: Only update after both calculations succeeded.
@@ -50,11 +159,16 @@ impl AccountState {
         &self,
         transaction_id: TransactionId,
         amount: Decimal,
+        limits: &Limits,
     ) -> anyhow::Result<AuthorizedWithdrawal> {
         ensure!(amount > Decimal::ZERO, "withdrawal amount should be > 0");
+        ensure!(
+            limits.max_withdrawal.is_none_or(|max| amount <= max),
+            "withdrawal amount exceeds limit"
+        );
 
-        // This was not mentioned in the requirements (forbid withdrawals for locked accounts), but seems like it would make sense.
-        // ensure!(!self.locked, "account is locked");
+        // note: Debits are rejected on frozen (locked) accounts, credits are still accepted.
+        ensure!(!self.is_locked(), "account is frozen");
 
         // This is directly from requirements.
         ensure!(self.available >= amount, "not enough funds");
@@ -64,15 +178,15 @@ impl AccountState {
         })
     }
 
-    pub(crate) fn withdraw(&mut self, withdrawal: PersistedTx<Withdrawal>) -> anyhow::Result<()> {
+    fn withdraw(&mut self, amount: Decimal) -> anyhow::Result<()> {
         // note: available cannot be negative?
         let new_available = self
             .available
-            .checked_sub(withdrawal.amount())
+            .checked_sub(amount)
             .context("available amount underflow")?;
         let new_total = self
             .total
-            .checked_sub(withdrawal.amount())
+            .checked_sub(amount)
             .context("total amount underflow")?;
 
         self.available = new_available;
@@ -80,28 +194,144 @@ impl AccountState {
         Ok(())
     }
 
-    pub(crate) fn dispute_deposit(&mut self, disputed: PersistedTx<Dispute>) -> anyhow::Result<()> {
-        // note: available cannot be negative?
+    fn reserve(&mut self, amount: Decimal) -> anyhow::Result<()> {
+        let new_available = self
+            .available
+            .checked_sub(amount)
+            .context("available amount underflow")?;
+        let new_reserved = self
+            .reserved
+            .checked_add(amount)
+            .context("reserved amount overflow")?;
+
+        self.available = new_available;
+        self.reserved = new_reserved;
+        Ok(())
+    }
+
+    fn withdraw_reserved(&mut self, amount: Decimal) -> anyhow::Result<()> {
+        let new_reserved = self
+            .reserved
+            .checked_sub(amount)
+            .context("reserved amount underflow")?;
+        let new_total = self
+            .total
+            .checked_sub(amount)
+            .context("total amount underflow")?;
+
+        self.reserved = new_reserved;
+        self.total = new_total;
+        Ok(())
+    }
+
+    fn capture(&mut self, amount: Decimal, released: Decimal) -> anyhow::Result<()> {
+        let reserved = amount
+            .checked_add(released)
+            .context("reserved amount overflow")?;
+        let new_reserved = self
+            .reserved
+            .checked_sub(reserved)
+            .context("reserved amount underflow")?;
+        let new_available = self
+            .available
+            .checked_add(released)
+            .context("available amount overflow")?;
+        let new_total = self
+            .total
+            .checked_sub(amount)
+            .context("total amount underflow")?;
+
+        self.reserved = new_reserved;
+        self.available = new_available;
+        self.total = new_total;
+        Ok(())
+    }
+
+    /// note: Fee can make available funds negative (e.g. a chargeback fee).
+    fn charge_fee(&mut self, amount: Decimal) -> anyhow::Result<()> {
+        let new_available = self
+            .available
+            .checked_sub(amount)
+            .context("available amount underflow")?;
+        let new_total = self
+            .total
+            .checked_sub(amount)
+            .context("total amount underflow")?;
+        let new_fees = self
+            .fees
+            .checked_add(amount)
+            .context("fees amount overflow")?;
+
+        self.available = new_available;
+        self.total = new_total;
+        self.fees = new_fees;
+        Ok(())
+    }
+
+    fn release_reserved(&mut self, amount: Decimal) -> anyhow::Result<()> {
+        let new_reserved = self
+            .reserved
+            .checked_sub(amount)
+            .context("reserved amount underflow")?;
+        let new_available = self
+            .available
+            .checked_add(amount)
+            .context("available amount overflow")?;
+
+        self.reserved = new_reserved;
+        self.available = new_available;
+        Ok(())
+    }
+
+    /// Checks that holding disputed `amount` keeps available funds within the overdraft limit
+    /// (`None` means unlimited).
+    pub(crate) fn authorize_dispute(
+        &self,
+        amount: Decimal,
+        overdraft_limit: Option<Decimal>,
+    ) -> anyhow::Result<()> {
+        let Some(limit) = overdraft_limit else {
+            return Ok(());
+        };
+        let available = self
+            .available
+            .checked_sub(amount)
+            .context("available amount underflow")?;
+        if limit.is_zero() {
+            ensure!(available >= Decimal::ZERO, "not enough funds to dispute");
+        } else {
+            let headroom = available.checked_add(limit);
+            ensure!(
+                headroom.is_some_and(|headroom| headroom >= Decimal::ZERO),
+                "overdraft limit exceeded"
+            );
+        }
+        Ok(())
+    }
+
+    fn dispute_deposit(&mut self, amount: Decimal) -> anyhow::Result<()> {
+        // note: Available can be negative (depending on overdraft policy), when disputed funds
+        // have already been withdrawn.
         self.available = self
             .available
-            .checked_sub(disputed.amount())
+            .checked_sub(amount)
             .context("available amount underflow")?;
         self.held = self
             .held
-            .checked_add(disputed.amount())
+            .checked_add(amount)
             .context("held amount overflow")?;
         Ok(())
     }
 
-    pub(crate) fn resolve_dispute(&mut self, resolved: PersistedTx<Resolve>) -> anyhow::Result<()> {
+    fn resolve_dispute(&mut self, amount: Decimal) -> anyhow::Result<()> {
         // note: held cannot be negative?
         let new_held = self
             .held
-            .checked_sub(resolved.amount())
+            .checked_sub(amount)
             .context("held amount underflow")?;
         let new_available = self
             .available
-            .checked_add(resolved.amount())
+            .checked_add(amount)
             .context("available amount overflow")?;
 
         self.held = new_held;
@@ -109,58 +339,262 @@ impl AccountState {
         Ok(())
     }
 
-    pub(crate) fn chargeback(
-        &mut self,
-        chargedback: PersistedTx<Chargeback>,
-    ) -> anyhow::Result<()> {
+    /// note: Account is locked by a separate `AccountLocked` event.
+    fn chargeback(&mut self, amount: Decimal) -> anyhow::Result<()> {
         // note: held cannot be negative?
         let new_held = self
             .held
-            .checked_sub(chargedback.amount())
+            .checked_sub(amount)
             .context("held amount underflow")?;
         let new_total = self
             .total
-            .checked_sub(chargedback.amount())
+            .checked_sub(amount)
             .context("total amount underflow")?;
 
         self.held = new_held;
         self.total = new_total;
-        self.locked = true;
         Ok(())
     }
 
-    pub(crate) fn available(&self) -> Decimal {
+    pub fn available(&self) -> Decimal {
         self.available
     }
 
-    pub(crate) fn held(&self) -> Decimal {
+    pub fn held(&self) -> Decimal {
         self.held
     }
 
-    pub(crate) fn total(&self) -> Decimal {
+    pub fn reserved(&self) -> Decimal {
+        self.reserved
+    }
+
+    pub fn total(&self) -> Decimal {
         self.total
     }
 
-    pub(crate) fn is_locked(&self) -> bool {
-        self.locked
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.status == AccountStatus::Frozen
     }
+
+    /// Reason of the last administrative change (lock, unlock, close).
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// Checks that the account can be closed (all balances are zero).
+    pub(crate) fn authorize_close(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.available.is_zero()
+                && self.held.is_zero()
+                && self.reserved.is_zero()
+                && self.total.is_zero(),
+            "account balance isn't zero"
+        );
+        Ok(())
+    }
+
+    pub fn fees(&self) -> Decimal {
+        self.fees
+    }
+
+    /// How much the account is overdrawn (negative available funds), 0 if it isn't.
+    pub fn exposure(&self) -> Decimal {
+        self.available.min(Decimal::ZERO).abs()
+    }
+}
+
+#[derive(
+    Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct ClientId(pub u64);
+
+/// What to do when a dispute would make available funds negative (disputed deposit has already
+/// been withdrawn).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum OverdraftPolicy {
+    /// Reject the dispute.
+    Reject,
+    /// Allow any negative available funds.
+    #[default]
+    Allow,
+    /// Allow negative available funds up to the client's limit.
+    Limit {
+        default: Decimal,
+        clients: FxHashMap<ClientId, Decimal>,
+    },
 }
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-pub(crate) struct ClientId(pub(crate) u16);
+impl OverdraftPolicy {
+    /// Overdraft limit of a client, `None` if unlimited.
+    pub fn limit(&self, client_id: ClientId) -> Option<Decimal> {
+        match self {
+            Self::Reject => Some(Decimal::ZERO),
+            Self::Allow => None,
+            Self::Limit { default, clients } => {
+                Some(clients.get(&client_id).copied().unwrap_or(*default))
+            }
+        }
+    }
+}
 
-#[derive(Default)]
-pub(crate) struct ClientDb {
+#[derive(Default, Clone)]
+pub struct ClientDb {
     clients: IndexMap<ClientId, AccountState>,
+    overdraft_policy: OverdraftPolicy,
+    /// Whether any funds have been reserved (held withdrawal or authorization).
+    has_reservations: bool,
+    /// Whether any fee has been charged.
+    has_fees: bool,
+    /// Whether any account has been changed administratively (or opened as pending).
+    has_admin_changes: bool,
+    /// Whether accounts have to be opened before they're used.
+    strict: bool,
 }
 
 impl ClientDb {
-    pub(crate) fn get_mut(&mut self, client_id: ClientId) -> &mut AccountState {
-        self.clients.entry(client_id).or_default()
+    pub fn with_overdraft_policy(mut self, overdraft_policy: OverdraftPolicy) -> Self {
+        self.overdraft_policy = overdraft_policy;
+        self
+    }
+
+    pub fn overdraft_policy(&self) -> &OverdraftPolicy {
+        &self.overdraft_policy
+    }
+
+    /// Require accounts to be opened (by an `open` operation or from an accounts file) before
+    /// they're used. Otherwise accounts are opened by their first accepted operation.
+    pub fn with_strict_accounts(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Database with the same configuration (overdraft policy, strict accounts), but no accounts.
+    pub(crate) fn without_accounts(&self) -> Self {
+        Self {
+            overdraft_policy: self.overdraft_policy.clone(),
+            strict: self.strict,
+            ..Self::default()
+        }
+    }
+
+    pub fn get(&self, client_id: ClientId) -> Option<&AccountState> {
+        self.clients.get(&client_id)
+    }
+
+    /// Updates accounts with an event, both accounts or neither of them for a transfer. Accounts
+    /// are only added once the event has been applied to them.
+    pub(crate) fn apply(&mut self, event: &Event) -> anyhow::Result<()> {
+        if matches!(event, Event::TransactionScoped { .. }) {
+            return Ok(());
+        }
+        let client_id = event.client();
+        if let Some(counterparty) = event.counterparty() {
+            let mut account = self.get(client_id).cloned().unwrap_or_default();
+            account.apply(client_id, event)?;
+            let mut other = self.get(counterparty).cloned().unwrap_or_default();
+            other.apply(counterparty, event)?;
+            self.clients.insert(client_id, account);
+            self.clients.insert(counterparty, other);
+        } else if let Some(account) = self.clients.get_mut(&client_id) {
+            account.apply(client_id, event)?;
+        } else {
+            let mut account = AccountState::default();
+            account.apply(client_id, event)?;
+            self.clients.insert(client_id, account);
+        }
+        match event {
+            Event::WithdrawalHeld { .. } | Event::Authorized { .. } => {
+                self.has_reservations = true;
+            }
+            Event::AccountOpened { reserved, .. } if !reserved.is_zero() => {
+                self.has_reservations = true;
+            }
+            Event::FeeCharged { .. } => self.has_fees = true,
+            Event::AccountLocked {
+                reason: Some(_), ..
+            }
+            | Event::AccountUnlocked {
+                reason: Some(_), ..
+            }
+            | Event::AccountClosed { .. }
+            | Event::AccountOpened {
+                status: AccountStatus::Pending,
+                ..
+            } => self.has_admin_changes = true,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Whether any withdrawal has been put on hold or authorized (so accounts may have reserved
+    /// funds).
+    pub fn has_reservations(&self) -> bool {
+        self.has_reservations
+    }
+
+    /// Whether any fee has been charged (so accounts may have paid fees).
+    pub fn has_fees(&self) -> bool {
+        self.has_fees
+    }
+
+    /// Whether any account has been changed administratively (so accounts may be closed or have
+    /// a reason).
+    pub fn has_admin_changes(&self) -> bool {
+        self.has_admin_changes
+    }
+
+    /// Total of fees charged to all clients.
+    pub fn fee_revenue(&self) -> Decimal {
+        self.clients.values().fold(Decimal::ZERO, |total, state| {
+            total.saturating_add(state.fees)
+        })
     }
 
     /// Clients are returned in deterministic order.
-    pub(crate) fn all(&self) -> impl Iterator<Item = (ClientId, &AccountState)> + use<'_> {
+    pub fn all(&self) -> impl Iterator<Item = (ClientId, &AccountState)> + use<'_> {
         self.clients.iter().map(|(id, state)| (*id, state))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_balances;
+
+    #[test]
+    fn test_failed_event_adds_no_account() {
+        let mut clients = ClientDb::default();
+        let deposit = Event::Deposited {
+            client: ClientId(1),
+            tx: TransactionId(1),
+            amount: Decimal::MAX,
+            timestamp: None,
+        };
+        clients.apply(&deposit).unwrap();
+
+        // destination account overflows, source account (new) isn't added
+        let transfer = Event::Transferred {
+            client: ClientId(2),
+            to: ClientId(1),
+            tx: TransactionId(2),
+            amount: Decimal::ONE,
+            timestamp: None,
+        };
+        assert!(clients.apply(&transfer).is_err());
+
+        let mut output = Vec::new();
+        write_balances(&clients, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,79228162514264337593543950335,0,79228162514264337593543950335,false\n"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    AccountState, AccountStatus, ClientDb, ClientId, Engine, ScopedTxId, TransactionId,
+    TransactionStatus, DECIMAL_PLACES,
+};
+
+/// Balances and status of an account, as compared by [`diff_states`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct AccountSnapshot {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub reserved: Decimal,
+    pub total: Decimal,
+    pub status: AccountStatus,
+}
+
+impl AccountSnapshot {
+    fn new(state: &AccountState) -> Self {
+        Self {
+            available: state.available().round_dp(DECIMAL_PLACES),
+            held: state.held().round_dp(DECIMAL_PLACES),
+            reserved: state.reserved().round_dp(DECIMAL_PLACES),
+            total: state.total().round_dp(DECIMAL_PLACES),
+            status: state.status(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum ClientChange {
+    Added {
+        client: ClientId,
+        after: AccountSnapshot,
+    },
+    Removed {
+        client: ClientId,
+        before: AccountSnapshot,
+    },
+    Changed {
+        client: ClientId,
+        before: AccountSnapshot,
+        after: AccountSnapshot,
+    },
+}
+
+/// Change of a transaction (status `None` if the id is recorded, but its status isn't stored),
+/// by its id in input.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum TransactionChange {
+    Added {
+        #[serde(flatten)]
+        tx: ScopedTxId,
+        after: Option<TransactionStatus>,
+    },
+    Removed {
+        #[serde(flatten)]
+        tx: ScopedTxId,
+        before: Option<TransactionStatus>,
+    },
+    Changed {
+        #[serde(flatten)]
+        tx: ScopedTxId,
+        before: Option<TransactionStatus>,
+        after: Option<TransactionStatus>,
+    },
+}
+
+/// Number of changes by kind.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct DiffSummary {
+    pub clients_added: u64,
+    pub clients_removed: u64,
+    pub clients_changed: u64,
+    pub transactions_added: u64,
+    pub transactions_removed: u64,
+    pub transactions_changed: u64,
+}
+
+impl DiffSummary {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Differences between two engine states.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StateDiff {
+    pub summary: DiffSummary,
+    /// Removed and changed clients (in `before` order), then added ones.
+    pub clients: Vec<ClientChange>,
+    /// Changed transactions, by (scoped) id.
+    pub transactions: Vec<TransactionChange>,
+}
+
+/// Compares two engine states (e.g. replayed from event logs of two environments).
+pub fn diff_states(before: &Engine, after: &Engine) -> StateDiff {
+    let mut diff = StateDiff::default();
+    diff_clients(before.clients(), after.clients(), &mut diff);
+    diff_transactions(before, after, &mut diff);
+    diff
+}
+
+fn diff_clients(before: &ClientDb, after: &ClientDb, diff: &mut StateDiff) {
+    let summary = &mut diff.summary;
+    for (client, state) in before.all() {
+        let before = AccountSnapshot::new(state);
+        match after.get(client).map(AccountSnapshot::new) {
+            None => {
+                summary.clients_removed = summary.clients_removed.saturating_add(1);
+                diff.clients.push(ClientChange::Removed { client, before });
+            }
+            Some(after) if after != before => {
+                summary.clients_changed = summary.clients_changed.saturating_add(1);
+                diff.clients.push(ClientChange::Changed {
+                    client,
+                    before,
+                    after,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (client, state) in after.all() {
+        if before.get(client).is_none() {
+            summary.clients_added = summary.clients_added.saturating_add(1);
+            diff.clients.push(ClientChange::Added {
+                client,
+                after: AccountSnapshot::new(state),
+            });
+        }
+    }
+}
+
+/// Compares transactions by their ids in input. Internal ids of scoped transactions depend on
+/// the order they were created in, so they're matched by scoped id instead.
+fn diff_transactions(before: &Engine, after: &Engine, diff: &mut StateDiff) {
+    let status = |engine: &Engine, tx: TransactionId| {
+        engine
+            .transactions()
+            .ids()
+            .contains(tx.0)
+            .then(|| engine.transactions().status(tx))
+    };
+    if before.has_global_ids() && after.has_global_ids() {
+        for id in before.transactions().ids() | after.transactions().ids() {
+            let tx = TransactionId(id);
+            push_transaction_change(
+                before.scoped_id(tx),
+                status(before, tx),
+                status(after, tx),
+                diff,
+            );
+        }
+        return;
+    }
+    let mut txs = BTreeMap::<ScopedTxId, (Option<TransactionId>, Option<TransactionId>)>::new();
+    for tx in before.transactions().ids().iter().map(TransactionId) {
+        txs.entry(before.scoped_id(tx)).or_default().0 = Some(tx);
+    }
+    for tx in after.transactions().ids().iter().map(TransactionId) {
+        txs.entry(after.scoped_id(tx)).or_default().1 = Some(tx);
+    }
+    for (tx, (before_tx, after_tx)) in txs {
+        let before_status = before_tx.and_then(|id| status(before, id));
+        let after_status = after_tx.and_then(|id| status(after, id));
+        push_transaction_change(tx, before_status, after_status, diff);
+    }
+}
+
+/// Adds a change of a transaction, given its status before and after (`None` if it doesn't
+/// exist).
+fn push_transaction_change(
+    tx: ScopedTxId,
+    before: Option<Option<TransactionStatus>>,
+    after: Option<Option<TransactionStatus>>,
+    diff: &mut StateDiff,
+) {
+    let summary = &mut diff.summary;
+    let change = match (before, after) {
+        (Some(before), None) => {
+            summary.transactions_removed = summary.transactions_removed.saturating_add(1);
+            TransactionChange::Removed { tx, before }
+        }
+        (None, Some(after)) => {
+            summary.transactions_added = summary.transactions_added.saturating_add(1);
+            TransactionChange::Added { tx, after }
+        }
+        (Some(before), Some(after)) if before != after => {
+            summary.transactions_changed = summary.transactions_changed.saturating_add(1);
+            TransactionChange::Changed { tx, before, after }
+        }
+        _ => return,
+    };
+    diff.transactions.push(change);
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+    use crate::{process_csv_into_engine, Options, TxScope};
+
+    #[test]
+    fn test_diff_states() {
+        const BEFORE: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 2, 2.0
+            withdrawal, 1, 3, 1.0
+            deposit, 3, 4, 1.0
+        "};
+
+        const AFTER: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 2, 2.0
+            dispute, 2, 2,
+            deposit, 4, 5, 1.0
+        "};
+
+        let engine = |input: &str| {
+            process_csv_into_engine(input.as_bytes(), Options::default())
+                .unwrap()
+                .0
+        };
+        let before = engine(BEFORE);
+        let after = engine(AFTER);
+
+        assert!(diff_states(&before, &before).summary.is_empty());
+
+        let diff = diff_states(&before, &after);
+        assert_eq!(
+            diff.summary,
+            DiffSummary {
+                clients_added: 1,
+                clients_removed: 1,
+                clients_changed: 2,
+                transactions_added: 1,
+                transactions_removed: 2,
+                transactions_changed: 1,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&diff.transactions).unwrap(),
+            serde_json::json!([
+                {"change": "changed", "tx": 2, "before": "deposited", "after": "disputed"},
+                {"change": "removed", "tx": 3, "before": "withdrawn"},
+                {"change": "removed", "tx": 4, "before": "deposited"},
+                {"change": "added", "tx": 5, "after": "deposited"},
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(&diff.clients[1]).unwrap(),
+            serde_json::json!({
+                "change": "changed",
+                "client": 2,
+                "before": {"available": "2", "held": "0", "reserved": "0", "total": "2", "status": "open"},
+                "after": {"available": "0", "held": "2", "reserved": "0", "total": "2", "status": "open"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_diff_scoped_ids() {
+        const BEFORE: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 1, 2.0
+        "};
+
+        // internal ids differ, transactions are created in different order
+        const AFTER: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 2, 1, 2.0
+            deposit, 1, 1, 5.0
+            dispute, 2, 1,
+            deposit, 3, 1, 1.0
+        "};
+
+        let engine = |input: &str| {
+            let options = Options {
+                tx_scope: TxScope::Client,
+                ..Options::default()
+            };
+            process_csv_into_engine(input.as_bytes(), options)
+                .unwrap()
+                .0
+        };
+        let before = engine(BEFORE);
+        let after = engine(AFTER);
+
+        let diff = diff_states(&before, &after);
+        assert_eq!(diff.summary.transactions_added, 1);
+        assert_eq!(diff.summary.transactions_changed, 1);
+        assert_eq!(
+            serde_json::to_value(&diff.transactions).unwrap(),
+            serde_json::json!([
+                {"change": "changed", "client": 2, "tx": 1, "before": "deposited", "after": "disputed"},
+                {"change": "added", "client": 3, "tx": 1, "after": "deposited"},
+            ])
+        );
+    }
+}
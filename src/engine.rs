@@ -0,0 +1,830 @@
+use std::io;
+
+use anyhow::{bail, ensure, Context};
+use rust_decimal::Decimal;
+
+use crate::{
+    accounts::OpeningAccount,
+    client::{AccountState, AccountStatus, OverdraftPolicy},
+    event::{read_events, Event, EventRecord},
+    fees::FeeSchedule,
+    limits::{LimitsConfig, RiskControls},
+    rules::{Rule, RuleSet},
+    scope::{ScopedIds, ScopedTxId, TxScope},
+    transaction::{DisputeExpiry, DisputePolicy, Submission, TransactionStatus},
+    ClientDb, ClientId, Operation, OperationType, Outcome, TransactionDb, TransactionId,
+};
+
+pub(crate) enum Decision {
+    /// Events to apply, in order.
+    Apply(Vec<Event>),
+    Duplicate,
+}
+
+/// Client whose account a dispute (resolve, chargeback) of a transaction affects, and the
+/// source client for a transfer. Only the transaction's client can dispute it, a transfer is
+/// disputed like a deposit into the destination account, by either of its clients.
+fn dispute_parties(
+    transactions: &TransactionDb,
+    client: ClientId,
+    tx: TransactionId,
+) -> anyhow::Result<(ClientId, Option<ClientId>)> {
+    let Some((from, to)) = transactions.transfer_parties(tx) else {
+        ensure!(
+            transactions.client(tx).is_none_or(|owner| owner == client),
+            "transaction belongs to another client"
+        );
+        return Ok((client, None));
+    };
+    ensure!(
+        client == from || client == to,
+        "transaction belongs to another client"
+    );
+    Ok((to, Some(from)))
+}
+
+/// Validates an operation against current state and turns it into events. Doesn't change
+/// anything, so a rejected operation leaves no trace.
+pub(crate) fn decide(
+    clients: &ClientDb,
+    transactions: &TransactionDb,
+    risk: &RiskControls,
+    operation: &Operation,
+) -> anyhow::Result<Decision> {
+    let client = operation.client;
+    let tx = operation.tx;
+    let limits = risk.limits(client);
+    let empty = AccountState::default();
+    let account = match clients.get(client) {
+        Some(account) => account,
+        None => {
+            ensure!(
+                !clients.is_strict() || operation.op_type == OperationType::Open,
+                "account does not exist"
+            );
+            &empty
+        }
+    };
+    match account.status() {
+        AccountStatus::Open | AccountStatus::Frozen => {}
+        AccountStatus::Pending => ensure!(
+            operation.op_type == OperationType::Open,
+            "account isn't open"
+        ),
+        AccountStatus::Closed => bail!("account is closed"),
+    }
+    let events = match operation.op_type {
+        OperationType::Open => {
+            ensure!(
+                clients
+                    .get(client)
+                    .is_none_or(|account| account.status() == AccountStatus::Pending),
+                "account already exists"
+            );
+            vec![Event::AccountOpened {
+                client,
+                status: AccountStatus::Open,
+                available: Decimal::ZERO,
+                held: Decimal::ZERO,
+                reserved: Decimal::ZERO,
+            }]
+        }
+        OperationType::Deposit => {
+            let amount = operation.amount.context("no amount for deposit")?;
+            if transactions.check_deposit(client, tx, amount)? == Submission::Repeated {
+                return Ok(Decision::Duplicate);
+            }
+            ensure!(amount > Decimal::ZERO, "deposit amount must be > 0");
+            let amount = transactions.round_amount(amount)?;
+            ensure!(amount > Decimal::ZERO, "deposit amount is too small");
+            ensure!(
+                limits.max_deposit.is_none_or(|max| amount <= max),
+                "deposit amount exceeds limit"
+            );
+            vec![Event::Deposited {
+                client,
+                tx,
+                amount,
+                timestamp: operation.timestamp,
+            }]
+        }
+        OperationType::Withdrawal => {
+            let amount = operation.amount.context("no amount for withdrawal")?;
+            if transactions.check_withdrawal(client, tx, amount)? == Submission::Repeated {
+                return Ok(Decision::Duplicate);
+            }
+            ensure!(amount > Decimal::ZERO, "withdrawal amount should be > 0");
+            let amount = transactions.round_amount(amount)?;
+            ensure!(amount > Decimal::ZERO, "withdrawal amount is too small");
+
+            let withdrawal = account.authorize_withdrawal(tx, amount, &limits)?;
+            risk.check_withdrawal(&limits, operation, amount)?;
+            vec![Event::Withdrawn {
+                client,
+                tx: withdrawal.transaction_id(),
+                amount: *withdrawal.amount(),
+            }]
+        }
+        OperationType::Dispute => {
+            ensure!(
+                operation.amount.is_none(),
+                "amount isn't expected for dispute"
+            );
+            let amount = transactions.check_dispute(tx, operation.timestamp)?;
+            let (client, _) = dispute_parties(transactions, client, tx)?;
+            let account = clients.get(client).unwrap_or(&empty);
+            account.authorize_dispute(amount, clients.overdraft_policy().limit(client))?;
+            vec![Event::DisputeOpened {
+                client,
+                tx,
+                amount,
+                timestamp: operation.timestamp,
+            }]
+        }
+        OperationType::Resolve => {
+            ensure!(
+                operation.amount.is_none(),
+                "amount isn't expected for resolve"
+            );
+            let amount = transactions.check_resolve(tx, operation.timestamp)?;
+            let (client, _) = dispute_parties(transactions, client, tx)?;
+            vec![Event::DisputeResolved { client, tx, amount }]
+        }
+        OperationType::Chargeback => {
+            ensure!(
+                operation.amount.is_none(),
+                "amount isn't expected for chargeback"
+            );
+            let amount = transactions.check_chargeback(tx, operation.timestamp)?;
+            let (client, refund_to) = dispute_parties(transactions, client, tx)?;
+            vec![
+                Event::ChargedBack {
+                    client,
+                    tx,
+                    amount,
+                    refund_to,
+                },
+                Event::AccountLocked {
+                    client,
+                    reason: None,
+                },
+            ]
+        }
+        OperationType::Reversal => {
+            ensure!(
+                operation.amount.is_none(),
+                "amount isn't expected for reversal"
+            );
+            let amount = transactions.check_reversal(tx)?;
+            let (client, refund_from) = dispute_parties(transactions, client, tx)?;
+            let mut events = vec![Event::ChargebackReversed {
+                client,
+                tx,
+                amount,
+                refund_from,
+            }];
+            let locked = clients.get(client).is_some_and(AccountState::is_locked);
+            if locked && transactions.dispute_policy().unlock_on_reversal {
+                events.push(Event::AccountUnlocked {
+                    client,
+                    reason: None,
+                });
+            }
+            events
+        }
+        OperationType::Lock => {
+            let reason = operation.reason.clone().context("no reason for lock")?;
+            ensure!(!account.is_locked(), "account is already locked");
+            vec![Event::AccountLocked {
+                client,
+                reason: Some(reason),
+            }]
+        }
+        OperationType::Unlock => {
+            ensure!(account.is_locked(), "account isn't locked");
+            vec![Event::AccountUnlocked {
+                client,
+                reason: operation.reason.clone(),
+            }]
+        }
+        OperationType::Close => {
+            account.authorize_close()?;
+            vec![Event::AccountClosed {
+                client,
+                reason: operation.reason.clone(),
+            }]
+        }
+        OperationType::Approve => {
+            ensure!(
+                operation.amount.is_none(),
+                "amount isn't expected for approve"
+            );
+            let amount = transactions.check_review(client, tx)?;
+            ensure!(!account.is_locked(), "account is frozen");
+            vec![Event::WithdrawalApproved { client, tx, amount }]
+        }
+        OperationType::Decline => {
+            ensure!(
+                operation.amount.is_none(),
+                "amount isn't expected for decline"
+            );
+            let amount = transactions.check_review(client, tx)?;
+            vec![Event::WithdrawalDeclined { client, tx, amount }]
+        }
+        OperationType::Transfer => {
+            let amount = operation.amount.context("no amount for transfer")?;
+            let to = operation.to.context("no destination for transfer")?;
+            ensure!(to != client, "transfer to the same client");
+            match clients.get(to).map(AccountState::status) {
+                Some(AccountStatus::Open | AccountStatus::Frozen) => {}
+                Some(_) => bail!("destination account isn't open"),
+                None => ensure!(!clients.is_strict(), "destination account does not exist"),
+            }
+            if transactions.check_transfer(client, to, tx, amount)? == Submission::Repeated {
+                return Ok(Decision::Duplicate);
+            }
+            ensure!(amount > Decimal::ZERO, "transfer amount should be > 0");
+            let amount = transactions.round_amount(amount)?;
+            ensure!(amount > Decimal::ZERO, "transfer amount is too small");
+
+            // note: Transfer is checked like a withdrawal from the source and a deposit into the
+            // destination.
+            let transfer = account.authorize_withdrawal(tx, amount, &limits)?;
+            risk.check_withdrawal(&limits, operation, amount)?;
+            ensure!(
+                risk.limits(to).max_deposit.is_none_or(|max| amount <= max),
+                "deposit amount exceeds limit"
+            );
+            vec![Event::Transferred {
+                client,
+                to,
+                tx: transfer.transaction_id(),
+                amount: *transfer.amount(),
+                timestamp: operation.timestamp,
+            }]
+        }
+        OperationType::Authorize => {
+            let amount = operation.amount.context("no amount for authorize")?;
+            if transactions.check_authorization(client, tx, amount)? == Submission::Repeated {
+                return Ok(Decision::Duplicate);
+            }
+            ensure!(amount > Decimal::ZERO, "authorization amount should be > 0");
+            let amount = transactions.round_amount(amount)?;
+            ensure!(amount > Decimal::ZERO, "authorization amount is too small");
+
+            // note: Authorization is checked like a withdrawal, it's one once captured.
+            let authorization = account.authorize_withdrawal(tx, amount, &limits)?;
+            risk.check_withdrawal(&limits, operation, amount)?;
+            vec![Event::Authorized {
+                client,
+                tx: authorization.transaction_id(),
+                amount: *authorization.amount(),
+            }]
+        }
+        OperationType::Capture => {
+            let authorized = transactions.check_authorized(client, tx)?;
+            // note: Reserved funds can still be released (decline, void) on a frozen account,
+            // but not debited.
+            ensure!(!account.is_locked(), "account is frozen");
+            let amount = match operation.amount {
+                Some(amount) => {
+                    ensure!(amount > Decimal::ZERO, "capture amount should be > 0");
+                    let amount = transactions.round_amount(amount)?;
+                    ensure!(amount > Decimal::ZERO, "capture amount is too small");
+                    ensure!(
+                        amount <= authorized,
+                        "capture amount exceeds authorized amount"
+                    );
+                    amount
+                }
+                None => authorized,
+            };
+            let released = authorized
+                .checked_sub(amount)
+                .context("released amount underflow")?;
+            vec![Event::Captured {
+                client,
+                tx,
+                amount,
+                released,
+            }]
+        }
+        OperationType::Void => {
+            ensure!(operation.amount.is_none(), "amount isn't expected for void");
+            let amount = transactions.check_authorized(client, tx)?;
+            vec![Event::Voided { client, tx, amount }]
+        }
+    };
+    risk.check_rate(&limits, operation)?;
+    Ok(Decision::Apply(events))
+}
+
+/// Applies an event to state. Account is updated first, it's the only part which can fail
+/// for a valid event (arithmetic overflow, or too many clients, which is checked up front), so a
+/// failed event changes nothing.
+pub(crate) fn apply(
+    clients: &mut ClientDb,
+    transactions: &mut TransactionDb,
+    event: &Event,
+) -> anyhow::Result<()> {
+    transactions.check_capacity()?;
+    clients.apply(event)?;
+    transactions.apply(event)
+}
+
+/// Payment engine: account and transaction state are projections of the event log, which is
+/// built from accepted operations.
+pub struct Engine {
+    clients: ClientDb,
+    transactions: TransactionDb,
+    risk: RiskControls,
+    rules: RuleSet,
+    fees: FeeSchedule,
+    admin: bool,
+    tx_ids: ScopedIds,
+    /// Sequence number of the next event.
+    next_seq: u64,
+    /// Events produced by the last processed operation.
+    last_events: Vec<EventRecord>,
+    /// Rules which flagged the last processed operation.
+    last_flags: Vec<usize>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            clients: ClientDb::default(),
+            transactions: TransactionDb::default(),
+            risk: RiskControls::default(),
+            rules: RuleSet::default(),
+            fees: FeeSchedule::default(),
+            admin: false,
+            tx_ids: ScopedIds::default(),
+            next_seq: 1,
+            last_events: Vec::new(),
+            last_flags: Vec::new(),
+        }
+    }
+}
+
+impl Engine {
+    /// Rebuilds state from an event log, applying events with `seq <= until_seq` (all of them
+    /// if `None`).
+    pub fn replay<R: io::Read>(log: R, until_seq: Option<u64>) -> anyhow::Result<Self> {
+        let mut engine = Self::default();
+        for record in read_events(log) {
+            let record = record?;
+            if until_seq.is_some_and(|until_seq| record.seq > until_seq) {
+                break;
+            }
+            engine.apply(&record)?;
+        }
+        Ok(engine)
+    }
+
+    pub fn clients(&self) -> &ClientDb {
+        &self.clients
+    }
+
+    pub fn transactions(&self) -> &TransactionDb {
+        &self.transactions
+    }
+
+    /// Sequence number of the last applied event (0 if there are none).
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq.saturating_sub(1)
+    }
+
+    /// Accept administrative operations (lock, unlock, close), they're rejected by default.
+    pub fn with_admin(mut self, admin: bool) -> Self {
+        self.admin = admin;
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.risk = RiskControls::new(limits);
+        self
+    }
+
+    /// Registers a rule, rules are evaluated in order they're registered.
+    pub fn with_rule(mut self, rule: Box<dyn Rule>) -> Self {
+        self.rules.add(rule);
+        self
+    }
+
+    pub fn with_overdraft_policy(mut self, overdraft_policy: OverdraftPolicy) -> Self {
+        self.clients = self.clients.with_overdraft_policy(overdraft_policy);
+        self
+    }
+
+    /// Scope of transaction ids, global by default. Otherwise events (and state) use internal
+    /// ids, which are assigned in order transactions are created and mapped to scoped ones by
+    /// [`Event::TransactionScoped`].
+    pub fn with_tx_scope(mut self, scope: TxScope) -> Self {
+        self.tx_ids = ScopedIds::new(scope);
+        self
+    }
+
+    /// Status of the operation's transaction (in its scope), `None` if it doesn't exist.
+    pub fn transaction_status(&self, operation: &Operation) -> Option<TransactionStatus> {
+        self.transactions.status(self.tx_ids.get(operation)?)
+    }
+
+    /// See [`ClientDb::with_strict_accounts`].
+    pub fn with_strict_accounts(mut self, strict: bool) -> Self {
+        self.clients = self.clients.with_strict_accounts(strict);
+        self
+    }
+
+    /// Opens an account before processing (e.g. from an accounts file, or balances output of an
+    /// earlier run) with its opening balances and status. It's recorded in the event log like any
+    /// other change.
+    pub fn open_account(&mut self, account: &OpeningAccount) -> anyhow::Result<()> {
+        self.last_events.clear();
+        self.last_flags.clear();
+        let client = account.client;
+        ensure!(
+            self.clients.get(client).is_none(),
+            "account {} already exists",
+            client.0
+        );
+        ensure!(
+            account.held >= Decimal::ZERO,
+            "account {}: held amount is negative",
+            client.0
+        );
+        ensure!(
+            account.reserved >= Decimal::ZERO,
+            "account {}: reserved amount is negative",
+            client.0
+        );
+        if let Some(total) = account.total {
+            let expected = account
+                .available
+                .checked_add(account.held)
+                .and_then(|sum| sum.checked_add(account.reserved));
+            ensure!(
+                expected == Some(total),
+                "account {}: total isn't available + held + reserved",
+                client.0
+            );
+        }
+
+        let status = account.status.unwrap_or_default();
+        let mut events = vec![Event::AccountOpened {
+            client,
+            status: match status {
+                AccountStatus::Pending => AccountStatus::Pending,
+                _ => AccountStatus::Open,
+            },
+            available: account.available.normalize(),
+            held: account.held.normalize(),
+            reserved: account.reserved.normalize(),
+        }];
+        let reason = account.reason.clone();
+        if status == AccountStatus::Closed {
+            ensure!(
+                account.available.is_zero() && account.held.is_zero() && account.reserved.is_zero(),
+                "account {}: closed account balance isn't zero",
+                client.0
+            );
+            events.push(Event::AccountClosed { client, reason });
+        } else if account.locked || status == AccountStatus::Frozen {
+            events.push(Event::AccountLocked { client, reason });
+        }
+        self.commit(events)
+    }
+
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.transactions = self.transactions.with_dispute_policy(dispute_policy);
+        self
+    }
+
+    /// Events produced by the last [`Self::process`] or [`Self::expire_next`] call.
+    pub fn last_events(&self) -> &[EventRecord] {
+        &self.last_events
+    }
+
+    /// Names of rules which flagged the last processed operation.
+    pub fn last_flags(&self) -> impl Iterator<Item = &str> + use<'_> {
+        self.last_flags.iter().map(|idx| self.rules.name(*idx))
+    }
+
+    /// Rejects an operation which couldn't be read completely (e.g. with an invalid timestamp)
+    /// without processing it.
+    pub(crate) fn reject(&mut self, error: anyhow::Error) -> anyhow::Result<Outcome> {
+        self.last_events.clear();
+        self.last_flags.clear();
+        Err(error)
+    }
+
+    pub fn process(&mut self, operation: &Operation) -> anyhow::Result<Outcome> {
+        self.last_events.clear();
+        self.last_flags.clear();
+        ensure!(
+            self.admin || !operation.op_type.is_admin(),
+            "administrative operations aren't enabled"
+        );
+        let original = operation;
+        let scoped;
+        let operation = match self.tx_ids.resolve(operation)? {
+            tx if tx == operation.tx => operation,
+            tx => {
+                scoped = Operation {
+                    tx,
+                    ..operation.clone()
+                };
+                &scoped
+            }
+        };
+        let events = match decide(&self.clients, &self.transactions, &self.risk, operation)? {
+            Decision::Apply(events) => events,
+            Decision::Duplicate => return Ok(Outcome::Duplicate),
+        };
+        let empty = AccountState::default();
+        let account = self.clients.get(operation.client).unwrap_or(&empty);
+        let evaluation = self.rules.evaluate(operation, account)?;
+        self.last_flags = evaluation.flags;
+        let (mut events, outcome) = match events.as_slice() {
+            // note: Hold only applies to withdrawals, other operations are just flagged.
+            &[Event::Withdrawn { client, tx, amount }] if evaluation.hold => (
+                vec![Event::WithdrawalHeld { client, tx, amount }],
+                Outcome::Held,
+            ),
+            _ => (events, Outcome::Applied),
+        };
+        if outcome == Outcome::Applied {
+            self.charge_fee(operation, &mut events)?;
+        }
+        events.extend(self.tx_ids.event(original, operation.tx));
+        self.commit(events)?;
+
+        // note: Held withdrawals, authorizations and transfers count towards daily withdrawal
+        // limit.
+        let withdrawn = self
+            .last_events
+            .iter()
+            .find_map(|record| match record.event {
+                Event::Withdrawn { amount, .. }
+                | Event::WithdrawalHeld { amount, .. }
+                | Event::Authorized { amount, .. }
+                | Event::Transferred { amount, .. } => Some(amount),
+                _ => None,
+            });
+        self.risk.record(operation, withdrawn);
+        self.rules.record(operation);
+        Ok(outcome)
+    }
+
+    /// Closes the oldest dispute which exceeded maximum duration before `now`, as configured by
+    /// [`DisputePolicy::on_expiry`]. Returns the synthetic resolve/chargeback operation (dated
+    /// at the deadline), `None` if there are no expired disputes.
+    ///
+    /// Should be called (until it returns `None`) before processing an operation at `now`.
+    pub fn expire_next(&mut self, now: u32) -> anyhow::Result<Option<Operation>> {
+        self.last_events.clear();
+        self.last_flags.clear();
+        let Some(on_expiry) = self.transactions.dispute_policy().on_expiry else {
+            return Ok(None);
+        };
+        let Some((client, tx, deadline)) = self.transactions.expired_dispute(now) else {
+            return Ok(None);
+        };
+        let operation = Operation {
+            op_type: match on_expiry {
+                DisputeExpiry::Resolve => OperationType::Resolve,
+                DisputeExpiry::Chargeback => OperationType::Chargeback,
+            },
+            client,
+            tx,
+            amount: None,
+            timestamp: Some(deadline),
+            to: None,
+            reason: None,
+            source: None,
+        };
+        // note: Limits and rules don't apply to synthetic operations.
+        let decision = decide(
+            &self.clients,
+            &self.transactions,
+            &RiskControls::default(),
+            &operation,
+        );
+        let context = || format!("cannot expire dispute of transaction {}", tx.0);
+        match decision.with_context(context)? {
+            Decision::Apply(mut events) => {
+                self.charge_fee(&operation, &mut events)
+                    .with_context(context)?;
+                self.commit(events).with_context(context)?;
+            }
+            Decision::Duplicate => bail!("{}: unexpected duplicate", context()),
+        }
+        Ok(Some(self.tx_ids.to_scoped(operation)))
+    }
+
+    /// Adds the fee for an operation to its events, charged to the account of the first event.
+    fn charge_fee(&self, operation: &Operation, events: &mut Vec<Event>) -> anyhow::Result<()> {
+        // note: Held withdrawal is charged the withdrawal fee once it's approved.
+        let op_type = match operation.op_type {
+            OperationType::Approve => OperationType::Withdrawal,
+            op_type => op_type,
+        };
+        let Some(first) = events.first() else {
+            return Ok(());
+        };
+        let client = first.client();
+        let Some(fee) = self.fees.fee(client, op_type) else {
+            return Ok(());
+        };
+        let fee = fee
+            .amount(first.amount().unwrap_or_default())
+            .context("fee amount overflow")?;
+        let fee = self.transactions.round_amount(fee)?;
+        if fee <= Decimal::ZERO {
+            return Ok(());
+        }
+        // note: Chargeback fee can overdraw any account, other fees can't overdraw a frozen one.
+        let frozen = op_type != OperationType::Chargeback
+            && self
+                .clients
+                .get(client)
+                .is_some_and(AccountState::is_locked);
+        if frozen
+            || matches!(
+                op_type,
+                OperationType::Withdrawal | OperationType::Authorize | OperationType::Transfer
+            )
+        {
+            let mut account = self.clients.get(client).cloned().unwrap_or_default();
+            for event in events.iter() {
+                account.apply(client, event)?;
+            }
+            if account.available() < fee {
+                // note: Credits are allowed on a frozen account, it's the fee which isn't.
+                bail!(if frozen {
+                    "not enough funds for fee (account is frozen)"
+                } else {
+                    "not enough funds for fee"
+                });
+            }
+        }
+        events.push(Event::FeeCharged {
+            client,
+            tx: operation.tx,
+            amount: fee,
+        });
+        Ok(())
+    }
+
+    /// Applies new events and records them.
+    fn commit(&mut self, events: Vec<Event>) -> anyhow::Result<()> {
+        for event in events {
+            self.apply_event(&event)?;
+            self.last_events.push(EventRecord {
+                seq: self.next_seq,
+                event,
+            });
+            self.next_seq = self.next_seq.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    /// Applies a recorded event, events have to be applied in sequence.
+    pub fn apply(&mut self, record: &EventRecord) -> anyhow::Result<()> {
+        ensure!(
+            record.seq == self.next_seq,
+            "unexpected event #{} (expected #{})",
+            record.seq,
+            self.next_seq
+        );
+        self.apply_event(&record.event)
+            .with_context(|| format!("cannot apply event #{}", record.seq))?;
+        self.next_seq = self.next_seq.saturating_add(1);
+        Ok(())
+    }
+
+    /// Applies an event to state, including scoped transaction ids.
+    fn apply_event(&mut self, event: &Event) -> anyhow::Result<()> {
+        match *event {
+            Event::TransactionScoped {
+                client,
+                tx,
+                scoped_tx,
+                to,
+                ref source,
+            } => self
+                .tx_ids
+                .insert(client, tx, scoped_tx, to, source.as_deref()),
+            _ => apply(&mut self.clients, &mut self.transactions, event),
+        }
+    }
+
+    /// Scoped id of a transaction (by its internal id), as in input.
+    pub(crate) fn scoped_id(&self, tx: TransactionId) -> ScopedTxId {
+        self.tx_ids.scoped_id(tx)
+    }
+
+    /// Whether transaction ids are global, so internal ids are the ones in input.
+    pub(crate) fn has_global_ids(&self) -> bool {
+        self.tx_ids.tx_scope() == TxScope::Global
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+    use crate::{process_csv_with, write_balances, Options};
+
+    const INPUT: &str = indoc! {"
+        type, client, tx, amount
+        deposit, 1, 1, 5.0
+        deposit, 2, 2, 2.0
+        withdrawal, 1, 3, 1.5
+        withdrawal, 2, 4, 3.0
+        dispute, 1, 1,
+        resolve, 1, 1,
+        dispute, 2, 2,
+        chargeback, 2, 2,
+    "};
+
+    fn event_log() -> Vec<u8> {
+        let mut events = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            io::sink(),
+            Options {
+                events: Some(&mut events),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        events
+    }
+
+    fn balances(engine: &Engine) -> String {
+        let mut output = Vec::new();
+        write_balances(engine.clients(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_replay() {
+        let events = event_log();
+        let engine = Engine::replay(events.as_slice(), None).unwrap();
+        assert_eq!(engine.last_seq(), 8);
+        assert_eq!(
+            balances(&engine),
+            indoc! {"
+                client,available,held,total,locked
+                1,3.5,0,3.5,false
+                2,0,0,0,true
+            "}
+        );
+
+        // after the first dispute
+        let engine = Engine::replay(events.as_slice(), Some(4)).unwrap();
+        assert_eq!(engine.last_seq(), 4);
+        assert_eq!(
+            balances(&engine),
+            indoc! {"
+                client,available,held,total,locked
+                1,-1.5,5,3.5,false
+                2,2,0,2,false
+            "}
+        );
+    }
+
+    #[test]
+    fn test_replay_matches_processing() {
+        let mut live = Engine::default();
+        for operation in crate::read_operations(INPUT.as_bytes()) {
+            let _ = live.process(&operation.unwrap());
+        }
+        let replayed = Engine::replay(event_log().as_slice(), None).unwrap();
+        assert_eq!(balances(&replayed), balances(&live));
+    }
+
+    #[test]
+    fn test_replay_invalid_log() {
+        // gap in sequence numbers
+        let log = indoc! {r#"
+            {"seq":1,"event":"deposited","client":1,"tx":1,"amount":"5"}
+            {"seq":3,"event":"deposited","client":1,"tx":2,"amount":"5"}
+        "#};
+        assert!(Engine::replay(log.as_bytes(), None).is_err());
+
+        // inconsistent event
+        let log = indoc! {r#"
+            {"seq":1,"event":"dispute_resolved","client":1,"tx":1,"amount":"5"}
+        "#};
+        assert!(Engine::replay(log.as_bytes(), None).is_err());
+    }
+}
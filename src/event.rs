@@ -0,0 +1,245 @@
+use std::io;
+
+use rust_decimal::Decimal;
+
+use crate::{deserialize_timestamp, AccountStatus, ClientId, TransactionId};
+
+/// Domain event, a fact about an accepted operation. Engine state is built by applying events
+/// in order, so the event log is enough to rebuild it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Deposited {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "deserialize_timestamp"
+        )]
+        timestamp: Option<u32>,
+    },
+    Withdrawn {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "deserialize_timestamp"
+        )]
+        timestamp: Option<u32>,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    /// Charged back from `client`. A transfer is charged back to its source (`refund_to`).
+    ChargedBack {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        refund_to: Option<ClientId>,
+    },
+    /// Account opened explicitly (by an operation or from an accounts file, with opening
+    /// balances).
+    AccountOpened {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "AccountStatus::is_open")]
+        status: AccountStatus,
+        #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+        available: Decimal,
+        #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+        held: Decimal,
+        #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+        reserved: Decimal,
+    },
+    AccountLocked {
+        client: ClientId,
+        /// Reason of a manual lock.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Chargeback reversed, the amount is credited to `client` again (and taken back from the
+    /// source of a transfer, `refund_from`).
+    ChargebackReversed {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        refund_from: Option<ClientId>,
+    },
+    AccountUnlocked {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    AccountClosed {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Withdrawal put on hold for review, amount is reserved.
+    WithdrawalHeld {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    WithdrawalApproved {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    WithdrawalDeclined {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    /// Fee for the operation on transaction `tx`.
+    FeeCharged {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    /// Funds moved from `client` to `to`.
+    Transferred {
+        client: ClientId,
+        to: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "deserialize_timestamp"
+        )]
+        timestamp: Option<u32>,
+    },
+    /// Funds reserved by an authorization.
+    Authorized {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    /// Authorization captured: `amount` is debited, the rest (`released`) is made available.
+    Captured {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        released: Decimal,
+    },
+    /// Authorization voided, its amount is made available.
+    Voided {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    },
+    /// Transaction `tx` (an internal id) was created as `scoped_tx` in the scope of `client` (and
+    /// transfer destination `to`), or of `source`, see [`TxScope`](crate::TxScope).
+    TransactionScoped {
+        client: ClientId,
+        tx: TransactionId,
+        scoped_tx: TransactionId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<ClientId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+    },
+}
+
+impl Event {
+    /// Client whose account is affected by the event (client of the transaction for
+    /// [`Event::TransactionScoped`], which doesn't affect accounts).
+    pub fn client(&self) -> ClientId {
+        match self {
+            Self::Deposited { client, .. }
+            | Self::Withdrawn { client, .. }
+            | Self::DisputeOpened { client, .. }
+            | Self::DisputeResolved { client, .. }
+            | Self::ChargedBack { client, .. }
+            | Self::AccountOpened { client, .. }
+            | Self::AccountLocked { client, .. }
+            | Self::ChargebackReversed { client, .. }
+            | Self::AccountUnlocked { client, .. }
+            | Self::AccountClosed { client, .. }
+            | Self::WithdrawalHeld { client, .. }
+            | Self::WithdrawalApproved { client, .. }
+            | Self::WithdrawalDeclined { client, .. }
+            | Self::Authorized { client, .. }
+            | Self::Captured { client, .. }
+            | Self::Voided { client, .. }
+            | Self::Transferred { client, .. }
+            | Self::FeeCharged { client, .. }
+            | Self::TransactionScoped { client, .. } => *client,
+        }
+    }
+
+    /// Amount the event is about, `None` if it has none.
+    pub fn amount(&self) -> Option<Decimal> {
+        match *self {
+            Self::Deposited { amount, .. }
+            | Self::Withdrawn { amount, .. }
+            | Self::DisputeOpened { amount, .. }
+            | Self::DisputeResolved { amount, .. }
+            | Self::ChargedBack { amount, .. }
+            | Self::WithdrawalHeld { amount, .. }
+            | Self::WithdrawalApproved { amount, .. }
+            | Self::WithdrawalDeclined { amount, .. }
+            | Self::Authorized { amount, .. }
+            | Self::Captured { amount, .. }
+            | Self::Voided { amount, .. }
+            | Self::Transferred { amount, .. }
+            | Self::FeeCharged { amount, .. }
+            | Self::ChargebackReversed { amount, .. } => Some(amount),
+            Self::AccountOpened { .. }
+            | Self::AccountLocked { .. }
+            | Self::AccountUnlocked { .. }
+            | Self::AccountClosed { .. }
+            | Self::TransactionScoped { .. } => None,
+        }
+    }
+
+    /// Other client whose account is affected by the event (transfers).
+    pub fn counterparty(&self) -> Option<ClientId> {
+        match *self {
+            Self::Transferred { to, .. } => Some(to),
+            Self::ChargedBack { refund_to, .. } => refund_to,
+            Self::ChargebackReversed { refund_from, .. } => refund_from,
+            _ => None,
+        }
+    }
+}
+
+/// Event with its position in the log. Sequence numbers start at 1 and have no gaps.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EventRecord {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Reads an event log (JSON Lines).
+pub fn read_events<R: io::Read>(reader: R) -> impl Iterator<Item = anyhow::Result<EventRecord>> {
+    serde_json::Deserializer::from_reader(io::BufReader::new(reader))
+        .into_iter()
+        .map(|result| Ok(result?))
+}
+
+/// Writes records as an event log (JSON Lines).
+pub(crate) fn write_events<'a, W: io::Write>(
+    mut writer: W,
+    records: impl IntoIterator<Item = &'a EventRecord>,
+) -> anyhow::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
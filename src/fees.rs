@@ -0,0 +1,133 @@
+use std::io;
+
+use anyhow::{ensure, Context};
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+
+use crate::{ClientId, OperationType};
+
+/// Fee for an operation, all parts optional: `flat + percent% of amount`, clamped to
+/// `[min, max]`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fee {
+    pub flat: Option<Decimal>,
+    /// Percentage of the operation amount.
+    pub percent: Option<Decimal>,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl Fee {
+    /// Fee for an operation of `amount`, `None` on overflow.
+    pub fn amount(&self, amount: Decimal) -> Option<Decimal> {
+        let mut fee = self.flat.unwrap_or_default();
+        if let Some(percent) = self.percent {
+            let variable = amount
+                .checked_mul(percent)?
+                .checked_div(Decimal::ONE_HUNDRED)?;
+            fee = fee.checked_add(variable)?;
+        }
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        Some(fee)
+    }
+}
+
+/// Fees by operation type.
+pub type Fees = FxHashMap<OperationType, Fee>;
+
+/// Fee schedule: default fees, overridden (per operation type) by the tier of a client.
+///
+/// ```json
+/// {
+///   "default": { "withdrawal": { "flat": "0.5", "percent": "1", "min": "1", "max": "10" } },
+///   "tiers": { "gold": { "withdrawal": { "flat": "0" } } },
+///   "clients": { "42": "gold" }
+/// }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    #[serde(default)]
+    pub default: Fees,
+    #[serde(default)]
+    pub tiers: FxHashMap<String, Fees>,
+    /// Tier of a client.
+    #[serde(default)]
+    pub clients: FxHashMap<ClientId, String>,
+}
+
+impl FeeSchedule {
+    /// Reads schedule from JSON.
+    pub fn from_reader<R: io::Read>(reader: R) -> anyhow::Result<Self> {
+        let schedule: Self =
+            serde_json::from_reader(io::BufReader::new(reader)).context("invalid fee schedule")?;
+        for tier in schedule.clients.values() {
+            ensure!(
+                schedule.tiers.contains_key(tier),
+                "unknown fee tier '{tier}'"
+            );
+        }
+        Ok(schedule)
+    }
+
+    /// Fee charged to a client for an operation type, `None` if there is none.
+    pub fn fee(&self, client_id: ClientId, op_type: OperationType) -> Option<&Fee> {
+        self.clients
+            .get(&client_id)
+            .and_then(|tier| self.tiers.get(tier)?.get(&op_type))
+            .or_else(|| self.default.get(&op_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let schedule = FeeSchedule::from_reader(
+            r#"{
+                "default": {
+                    "withdrawal": { "flat": "0.5", "percent": "1", "min": "1", "max": "10" },
+                    "chargeback": { "flat": "15" }
+                },
+                "tiers": { "gold": { "withdrawal": { "percent": "0.5" } } },
+                "clients": { "42": "gold" }
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let fee = |client, op_type, amount: i64| {
+            schedule
+                .fee(ClientId(client), op_type)
+                .and_then(|fee| fee.amount(Decimal::from(amount)))
+        };
+        assert_eq!(fee(1, OperationType::Withdrawal, 10), Some(Decimal::ONE));
+        assert_eq!(
+            fee(1, OperationType::Withdrawal, 100),
+            Some(Decimal::new(15, 1))
+        );
+        assert_eq!(
+            fee(1, OperationType::Withdrawal, 10_000),
+            Some(Decimal::TEN)
+        );
+        assert_eq!(
+            fee(42, OperationType::Withdrawal, 100),
+            Some(Decimal::new(5, 1))
+        );
+        assert_eq!(
+            fee(42, OperationType::Chargeback, 1),
+            Some(Decimal::from(15))
+        );
+        assert_eq!(fee(1, OperationType::Deposit, 100), None);
+
+        assert!(FeeSchedule::from_reader(r#"{"clients": {"1": "silver"}}"#.as_bytes()).is_err());
+    }
+}
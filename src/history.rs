@@ -0,0 +1,251 @@
+use crate::{client::AccountState, ClientDb, ClientId, Engine, Event};
+
+/// State of client accounts after a given row.
+struct Checkpoint {
+    row: u64,
+    /// Number of events recorded up to (and including) the row.
+    changes: usize,
+    clients: ClientDb,
+}
+
+/// Balance history, answers "what were balances after row N" without reprocessing the input.
+///
+/// Keeps events of every processed row and a copy of all accounts every `interval`
+/// rows, so a query only has to apply changes since the nearest checkpoint. Memory is 80 bytes
+/// per event on 64-bit platforms (`size_of::<(u64, Event)>()`, plus the reason of an
+/// administrative change) and a copy of accounts per checkpoint.
+pub struct BalanceHistory {
+    interval: u64,
+    changes: Vec<(u64, Event)>,
+    /// The first one is the initial state (no accounts, but configured like the engine's).
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl BalanceHistory {
+    pub fn new(interval: u64) -> Self {
+        Self {
+            interval: interval.max(1),
+            changes: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Records accounts opened before processing (they're part of the first row).
+    pub(crate) fn record_opening(&mut self, engine: &Engine) {
+        self.push_events(0, engine);
+    }
+
+    /// Records changes made by processing `row` (rows have to be recorded in order). A row can be
+    /// recorded several times (e.g. synthetic operations before the input one).
+    pub fn record(&mut self, row: u64, engine: &Engine) {
+        self.push_events(row, engine);
+        if row.saturating_add(1).is_multiple_of(self.interval) {
+            let checkpoint = Checkpoint {
+                row,
+                changes: self.changes.len(),
+                clients: engine.clients().clone(),
+            };
+            match self.checkpoints.last_mut() {
+                Some(last) if last.row == row => *last = checkpoint,
+                _ => self.checkpoints.push(checkpoint),
+            }
+        }
+    }
+
+    fn push_events(&mut self, row: u64, engine: &Engine) {
+        if self.checkpoints.is_empty() {
+            self.checkpoints.push(Checkpoint {
+                row: 0,
+                changes: 0,
+                clients: engine.clients().without_accounts(),
+            });
+        }
+        for record in engine.last_events() {
+            // note: Scoped ids don't affect balances.
+            if !matches!(record.event, Event::TransactionScoped { .. }) {
+                self.changes.push((row, record.event.clone()));
+            }
+        }
+    }
+
+    /// Nearest checkpoint at or before `row`.
+    fn checkpoint(&self, row: u64) -> Option<&Checkpoint> {
+        let idx = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.row <= row);
+        idx.checked_sub(1).map(|idx| &self.checkpoints[idx])
+    }
+
+    /// Changes after the checkpoint, up to (and including) `row`.
+    fn changes_since<'a>(
+        &'a self,
+        checkpoint: Option<&Checkpoint>,
+        row: u64,
+    ) -> impl Iterator<Item = &'a Event> + use<'a> {
+        let start = checkpoint.map_or(0, |checkpoint| checkpoint.changes);
+        self.changes[start..]
+            .iter()
+            .take_while(move |(change_row, _)| *change_row <= row)
+            .map(|(_, change)| change)
+    }
+
+    /// Balances of all clients after `row`.
+    pub fn balances_at(&self, row: u64) -> ClientDb {
+        let checkpoint = self.checkpoint(row);
+        let mut clients = checkpoint.map_or_else(ClientDb::default, |c| c.clients.clone());
+        for event in self.changes_since(checkpoint, row) {
+            clients
+                .apply(event)
+                .expect("recorded event was applied before");
+        }
+        clients
+    }
+
+    /// Balances of a single client after `row` (`None` if the account didn't exist yet).
+    pub fn account_at(&self, row: u64, client: ClientId) -> Option<AccountState> {
+        let checkpoint = self.checkpoint(row);
+        let mut account = checkpoint.and_then(|c| c.clients.get(client)).cloned();
+        for event in self.changes_since(checkpoint, row) {
+            if event.client() == client || event.counterparty() == Some(client) {
+                apply(account.get_or_insert_default(), client, event);
+            }
+        }
+        account
+    }
+}
+
+fn apply(account: &mut AccountState, client: ClientId, event: &Event) {
+    account
+        .apply(client, event)
+        .expect("recorded event was applied before");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use indoc::indoc;
+    use rust_decimal::Decimal;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+    use crate::{
+        process_csv_with, write_balances, DisputeExpiry, DisputePolicy, Options, OverdraftPolicy,
+    };
+
+    const INPUT: &str = indoc! {"
+        type, client, tx, amount
+        deposit, 1, 1, 5.0
+        deposit, 2, 2, 2.0
+        withdrawal, 3, 3, 1.0
+        withdrawal, 1, 4, 1.5
+        dispute, 1, 1,
+        deposit, 4, 5, 1.0
+        resolve, 1, 1,
+        dispute, 2, 2,
+        chargeback, 2, 2,
+    "};
+
+    fn balances(clients: &ClientDb) -> String {
+        let mut output = Vec::new();
+        write_balances(clients, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_balances_at() {
+        let mut history = BalanceHistory::new(3);
+        process_csv_with(
+            INPUT.as_bytes(),
+            io::sink(),
+            Options {
+                history: Some(&mut history),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(history.checkpoints.len(), 4);
+
+        for row in 0..9 {
+            let mut expected = Vec::new();
+            process_csv_with(
+                INPUT.as_bytes(),
+                &mut expected,
+                Options {
+                    until_row: Some(row),
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            let expected = String::from_utf8(expected).unwrap();
+            assert_eq!(balances(&history.balances_at(row)), expected, "row #{row}");
+        }
+
+        let account = history.account_at(4, ClientId(1)).unwrap();
+        assert_eq!(account.available(), Decimal::new(-15, 1));
+        assert_eq!(account.held(), Decimal::from(5));
+        let account = history.account_at(8, ClientId(2)).unwrap();
+        assert_eq!(account.total(), Decimal::ZERO);
+        assert!(account.is_locked());
+        assert!(history.account_at(8, ClientId(3)).is_none());
+        assert!(history.account_at(4, ClientId(4)).is_none());
+        assert!(history.account_at(5, ClientId(4)).is_some());
+    }
+
+    #[test]
+    fn test_balances_at_with_options() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 5.0, 100
+            withdrawal, 1, 2, 4.0, 100
+            deposit, 2, 3, 3.0, 100
+            dispute, 1, 1, , 100
+            dispute, 2, 3, , 150
+            deposit, 1, 4, 1.0, 300
+        "};
+
+        fn options<'a>() -> Options<'a> {
+            Options {
+                overdraft_policy: OverdraftPolicy::Limit {
+                    default: Decimal::from(3),
+                    clients: Default::default(),
+                },
+                dispute_policy: DisputePolicy {
+                    max_duration: Some(Duration::from_secs(10)),
+                    on_expiry: Some(DisputeExpiry::Resolve),
+                    ..DisputePolicy::default()
+                },
+                ..Options::default()
+            }
+        }
+
+        let mut history = BalanceHistory::new(6);
+        process_csv_with(
+            INPUT.as_bytes(),
+            io::sink(),
+            Options {
+                history: Some(&mut history),
+                ..options()
+            },
+        )
+        .unwrap();
+        // initial state, and row 5 (recorded after each of the 2 expired disputes and the deposit)
+        let rows: Vec<_> = history.checkpoints.iter().map(|c| c.row).collect();
+        assert_eq!(rows, [0, 5]);
+
+        for row in 0..6 {
+            let mut expected = Vec::new();
+            process_csv_with(
+                INPUT.as_bytes(),
+                &mut expected,
+                Options {
+                    until_row: Some(row),
+                    ..options()
+                },
+            )
+            .unwrap();
+            let expected = String::from_utf8(expected).unwrap();
+            assert_eq!(balances(&history.balances_at(row)), expected, "row #{row}");
+        }
+    }
+}
@@ -0,0 +1,2294 @@
+use std::io;
+
+use anyhow::{anyhow, ensure, Context};
+
+use rust_decimal::Decimal;
+
+pub use crate::{
+    accounts::{read_accounts, OpeningAccount},
+    client::{AccountState, AccountStatus, ClientDb, ClientId, OverdraftPolicy},
+    diff::{diff_states, AccountSnapshot, ClientChange, DiffSummary, StateDiff, TransactionChange},
+    engine::Engine,
+    event::{read_events, Event, EventRecord},
+    fees::{Fee, FeeSchedule, Fees},
+    history::BalanceHistory,
+    limits::{Limits, LimitsConfig, OperationRate},
+    outcome::{OutcomeEvent, OutcomeKind},
+    reader::{OperationReader, ReadError},
+    reconcile::{reconcile, write_differences, Difference, DifferenceKind},
+    rules::{read_rules, DeclarativeRule, RecentOperation, Rule, RuleAction, Verdict},
+    scope::{ScopedTxId, TxScope},
+    transaction::{DisputeExpiry, DisputePolicy, TransactionDb, TransactionId, TransactionStatus},
+};
+use crate::{engine::Decision, event::write_events, limits::RiskControls, outcome::OutcomeWriter};
+
+mod accounts;
+mod client;
+mod diff;
+mod engine;
+mod event;
+mod fees;
+mod history;
+mod limits;
+mod outcome;
+mod reader;
+mod reconcile;
+mod rules;
+mod scope;
+mod storage;
+mod transaction;
+
+/// Number of decimal places amounts are stored and output with.
+pub const DECIMAL_PLACES: u32 = 4;
+
+// note: Ideally we don't want "dispute/resolve/chargeback" to have `amount` field. And
+// we also want it to be non-optional for "deposit/withdrawal". This can be done with an
+// enum, howevever I couldn't get it to work quickly with csv deserialiazer. Another option
+// is to just have this type as serialize/deserialize intermediate type and build an enum
+// from it (as fallible operation).
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Operation {
+    #[serde(rename = "type")]
+    pub op_type: OperationType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Decimal>,
+    /// Unix time (seconds) of the operation, optional column.
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    pub timestamp: Option<u32>,
+    /// Destination client of a transfer, optional column.
+    #[serde(default)]
+    pub to: Option<ClientId>,
+    /// Reason of an administrative operation, optional column.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Source (e.g. partner) of the transaction, optional column, see [`TxScope::Source`].
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// Deserializes an optional timestamp, which has to be > 0 (stored transactions use 0 for "no
+/// timestamp") and fit into `u32` (until 2106).
+pub(crate) fn deserialize_timestamp<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    use serde::{de::Error, Deserialize};
+
+    let Some(timestamp) = Option::<u64>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    if timestamp == 0 {
+        return Err(D::Error::custom("timestamp should be > 0"));
+    }
+    u32::try_from(timestamp)
+        .map(Some)
+        .map_err(|_| D::Error::custom("timestamp is out of range (after 2106)"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Completes a withdrawal which was put on hold.
+    Approve,
+    /// Releases reserved funds of a withdrawal which was put on hold.
+    Decline,
+    /// Reserves funds for a later capture.
+    Authorize,
+    /// Debits (part of) authorized funds, the rest is released. Amount is optional, full
+    /// authorized amount is captured by default.
+    Capture,
+    /// Releases authorized funds.
+    Void,
+    /// Moves funds from `client` to `to`.
+    Transfer,
+    /// Reverses a chargeback (after arbitration), re-crediting the amount.
+    Reversal,
+    /// Freezes the account (administrative, with a reason).
+    Lock,
+    /// Unlocks the account (administrative).
+    Unlock,
+    /// Closes the account, only when its balances are zero (administrative).
+    Close,
+    /// Opens an account, required before it's used with strict accounts.
+    Open,
+}
+
+impl OperationType {
+    /// Whether it's an administrative operation, only accepted in admin mode.
+    pub fn is_admin(self) -> bool {
+        matches!(self, Self::Lock | Self::Unlock | Self::Close)
+    }
+}
+
+/// Result of an operation that wasn't rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Applied,
+    /// Identical re-submission of an already applied deposit/withdrawal, nothing changed.
+    Duplicate,
+    /// Synthetic resolve/chargeback of an expired dispute, see [`Engine::expire_next`].
+    Expired,
+    /// Withdrawal was put on hold by a rule, its amount is reserved until it's approved or
+    /// declined.
+    Held,
+}
+
+/// Processes a single operation, without recording events or checking limits (see [`Engine`]
+/// for that).
+pub fn process_operation(
+    clients: &mut ClientDb,
+    transactions: &mut TransactionDb,
+    operation: &Operation,
+) -> anyhow::Result<Outcome> {
+    ensure!(
+        !operation.op_type.is_admin(),
+        "administrative operations aren't enabled"
+    );
+    let events = match engine::decide(clients, transactions, &RiskControls::default(), operation)? {
+        Decision::Apply(events) => events,
+        Decision::Duplicate => return Ok(Outcome::Duplicate),
+    };
+    for event in &events {
+        engine::apply(clients, transactions, event)?;
+    }
+    Ok(Outcome::Applied)
+}
+
+#[derive(serde::Serialize)]
+struct ClientRow<'a> {
+    client: ClientId,
+    available: Decimal,
+    held: Decimal,
+    /// Only output once a withdrawal has been put on hold or authorized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserved: Option<Decimal>,
+    total: Decimal,
+    locked: bool,
+    /// Only output with an overdraft policy other than the default one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exposure: Option<Decimal>,
+    /// Only output once a fee has been charged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fees: Option<Decimal>,
+    /// Only output once an account has been changed administratively (or opened as pending).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<AccountStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<Option<&'a str>>,
+}
+
+/// Reads operations from CSV input (with headers, whitespace around fields is ignored).
+///
+/// This is the reference (serde) implementation, see [`OperationReader`] for a faster one.
+pub fn read_operations<R: io::Read>(reader: R) -> impl Iterator<Item = csv::Result<Operation>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .has_headers(true)
+        .from_reader(reader)
+        .into_deserialize()
+}
+
+/// Counters collected while processing input.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of input rows (operations) read.
+    pub rows: u64,
+    /// Number of operations that were rejected.
+    pub rejected: u64,
+    /// Number of re-submitted operations that were ignored.
+    pub duplicates: u64,
+    /// Number of disputes closed automatically after expiring.
+    pub expired_disputes: u64,
+    /// Number of accepted operations flagged by rules.
+    pub flagged: u64,
+    /// Number of withdrawals put on hold by rules.
+    pub held: u64,
+    /// Total of fees charged.
+    pub fee_revenue: Decimal,
+    /// Number of recorded transactions.
+    pub transactions: u64,
+    /// Number of stored (disputable) transactions.
+    pub disputable_transactions: u64,
+    /// Approximate number of bytes used to store transactions.
+    pub transaction_bytes: u64,
+}
+
+/// Optional behaviour of [`process_csv_with`].
+#[derive(Default)]
+pub struct Options<'a> {
+    /// Sink for per-operation outcomes ([`OutcomeEvent`] as JSON Lines).
+    pub outcomes: Option<&'a mut dyn io::Write>,
+    /// Sink for the event log ([`EventRecord`] as JSON Lines), see [`Engine::replay`].
+    pub events: Option<&'a mut dyn io::Write>,
+    /// Only process rows up to (and including) this index (0-based, not counting headers).
+    pub until_row: Option<u64>,
+    /// Stop processing at the first row with a timestamp after this one (Unix time, seconds).
+    pub until_time: Option<u32>,
+    /// Records balance history for point-in-time queries.
+    pub history: Option<&'a mut BalanceHistory>,
+    pub dispute_policy: DisputePolicy,
+    pub overdraft_policy: OverdraftPolicy,
+    pub limits: LimitsConfig,
+    /// Custom rules, evaluated in order.
+    pub rules: Vec<Box<dyn Rule>>,
+    pub fees: FeeSchedule,
+    /// Accept administrative operations (lock, unlock, close).
+    pub admin: bool,
+    /// Require accounts to be opened before they're used.
+    pub strict_accounts: bool,
+    /// Accounts opened before processing.
+    pub accounts: Vec<OpeningAccount>,
+    pub tx_scope: TxScope,
+}
+
+/// Optional outputs, updated after every (input or synthetic) operation.
+struct Sinks<'a> {
+    outcomes: Option<OutcomeWriter<&'a mut dyn io::Write>>,
+    events: Option<&'a mut dyn io::Write>,
+    history: Option<&'a mut BalanceHistory>,
+}
+
+impl Sinks<'_> {
+    fn record(
+        &mut self,
+        row: u64,
+        operation: &Operation,
+        result: &anyhow::Result<Outcome>,
+        engine: &Engine,
+    ) -> anyhow::Result<()> {
+        if let Some(events) = &mut self.events {
+            write_events(events, engine.last_events())?;
+        }
+        if let Some(history) = &mut self.history {
+            history.record(row, engine);
+        }
+        if let Some(outcomes) = &mut self.outcomes {
+            outcomes.write(row, operation, result, engine)?;
+        }
+        Ok(())
+    }
+
+    /// Records an account opened before processing.
+    fn record_opening(&mut self, engine: &Engine) -> anyhow::Result<()> {
+        if let Some(events) = &mut self.events {
+            write_events(events, engine.last_events())?;
+        }
+        if let Some(history) = &mut self.history {
+            history.record_opening(engine);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(outcomes) = &mut self.outcomes {
+            outcomes.flush()?;
+        }
+        if let Some(events) = &mut self.events {
+            events.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn process_csv<R: io::Read, W: io::Write>(reader: R, writer: W) -> anyhow::Result<Stats> {
+    process_csv_with(reader, writer, Options::default())
+}
+
+pub fn process_csv_with<R: io::Read, W: io::Write>(
+    reader: R,
+    writer: W,
+    options: Options<'_>,
+) -> anyhow::Result<Stats> {
+    let (engine, stats) = process_csv_into_engine(reader, options)?;
+    write_balances(engine.clients(), writer)?;
+    Ok(stats)
+}
+
+/// Processes operations like [`process_csv_with`], but returns the engine (final state) instead
+/// of writing balances.
+pub fn process_csv_into_engine<R: io::Read>(
+    reader: R,
+    options: Options<'_>,
+) -> anyhow::Result<(Engine, Stats)> {
+    process_inputs_into_engine([(None, reader)], options)
+}
+
+/// Processes several inputs in order, like [`process_csv_into_engine`]. Rows without a `source`
+/// get the source of their input (if any), see [`TxScope::Source`]. Rows are numbered across
+/// inputs.
+pub fn process_inputs_into_engine<R: io::Read>(
+    inputs: impl IntoIterator<Item = (Option<String>, R)>,
+    options: Options<'_>,
+) -> anyhow::Result<(Engine, Stats)> {
+    let mut engine = Engine::default()
+        .with_dispute_policy(options.dispute_policy)
+        .with_overdraft_policy(options.overdraft_policy)
+        .with_limits(options.limits)
+        .with_fee_schedule(options.fees)
+        .with_admin(options.admin)
+        .with_strict_accounts(options.strict_accounts)
+        .with_tx_scope(options.tx_scope);
+    for rule in options.rules {
+        engine = engine.with_rule(rule);
+    }
+    let mut stats = Stats::default();
+    let mut sinks = Sinks {
+        outcomes: options.outcomes.map(OutcomeWriter::new),
+        events: options.events,
+        history: options.history,
+    };
+
+    for account in options.accounts {
+        engine.open_account(&account)?;
+        sinks.record_opening(&engine)?;
+    }
+
+    // read & update client accounts
+    let mut rows = 0_u64..;
+    'inputs: for (source, reader) in inputs {
+        for (result, idx) in OperationReader::new(reader).zip(&mut rows) {
+            if options.until_row.is_some_and(|until_row| idx > until_row) {
+                break 'inputs;
+            }
+            // note: A row with an invalid timestamp is rejected, like one with an invalid amount.
+            let (mut operation, invalid) = match result {
+                Ok(operation) => (operation, None),
+                Err(ReadError::InvalidTimestamp { operation, error }) => (*operation, Some(error)),
+                Err(error) => return Err(error).with_context(|| format!("row #{idx}")),
+            };
+            if operation.source.is_none() {
+                operation.source.clone_from(&source);
+            }
+            if options
+                .until_time
+                .is_some_and(|until_time| operation.timestamp.is_some_and(|time| time > until_time))
+            {
+                break 'inputs;
+            }
+            stats.rows = stats.rows.saturating_add(1);
+            if let Some(now) = operation.timestamp {
+                while let Some(expired) = engine.expire_next(now)? {
+                    sinks.record(idx, &expired, &Ok(Outcome::Expired), &engine)?;
+                    stats.expired_disputes = stats.expired_disputes.saturating_add(1);
+                }
+            }
+            let result = match invalid {
+                Some(error) => engine.reject(anyhow!(error)),
+                None => engine.process(&operation),
+            };
+            sinks.record(idx, &operation, &result, &engine)?;
+            if engine.last_flags().next().is_some() {
+                stats.flagged = stats.flagged.saturating_add(1);
+            }
+            match result {
+                Ok(Outcome::Applied | Outcome::Expired) => {}
+                Ok(Outcome::Held) => stats.held = stats.held.saturating_add(1),
+                Ok(Outcome::Duplicate) => stats.duplicates = stats.duplicates.saturating_add(1),
+                Err(error) => {
+                    stats.rejected = stats.rejected.saturating_add(1);
+                    eprintln!("row #{idx}: {error}");
+                }
+            }
+        }
+    }
+    sinks.flush()?;
+
+    let transactions = engine.transactions();
+    stats.transactions = transactions.len();
+    stats.disputable_transactions = u64::try_from(transactions.disputable_len())?;
+    stats.transaction_bytes = u64::try_from(transactions.heap_size())?;
+    stats.fee_revenue = engine.clients().fee_revenue();
+    Ok((engine, stats))
+}
+
+/// Writes client balances as CSV, with an `exposure` column (how much the account is
+/// overdrawn) unless overdraft policy is the default one.
+pub fn write_balances<W: io::Write>(clients: &ClientDb, writer: W) -> anyhow::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_writer(writer);
+    for (client, state) in clients.all() {
+        writer.serialize(ClientRow {
+            client,
+            available: state.available().round_dp(DECIMAL_PLACES),
+            held: state.held().round_dp(DECIMAL_PLACES),
+            reserved: clients
+                .has_reservations()
+                .then(|| state.reserved().round_dp(DECIMAL_PLACES)),
+            total: state.total().round_dp(DECIMAL_PLACES),
+            locked: state.is_locked(),
+            exposure: (*clients.overdraft_policy() != OverdraftPolicy::Allow)
+                .then(|| state.exposure().round_dp(DECIMAL_PLACES)),
+            fees: clients
+                .has_fees()
+                .then(|| state.fees().round_dp(DECIMAL_PLACES)),
+            status: clients.has_admin_changes().then(|| state.status()),
+            reason: clients.has_admin_changes().then(|| state.reason()),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use indoc::indoc;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    /// Rows and errors of rejected operations in an outcome stream.
+    fn errors(outcomes: &[u8]) -> Vec<(u64, String)> {
+        serde_json::Deserializer::from_slice(outcomes)
+            .into_iter::<serde_json::Value>()
+            .map(Result::unwrap)
+            .filter_map(|outcome| {
+                Some((
+                    outcome["row"].as_u64()?,
+                    outcome["error"].as_str()?.to_owned(),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chargeback() {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+
+        // deposit 5.0
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Deposit,
+                client: ClientId(123),
+                tx: TransactionId(999),
+                amount: Some(5.into()),
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        // deposit 2.0
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Deposit,
+                client: ClientId(123),
+                tx: TransactionId(256),
+                amount: Some(2.into()),
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        // dispute
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Dispute,
+                client: ClientId(123),
+                tx: TransactionId(256),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        let client = clients.get(ClientId(123)).unwrap();
+        assert_eq!(client.available(), Decimal::from(5));
+        assert_eq!(client.held(), Decimal::from(2));
+        assert_eq!(client.total(), Decimal::from(7));
+        assert_eq!(client.is_locked(), false);
+
+        // chargeback
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Chargeback,
+                client: ClientId(123),
+                tx: TransactionId(256),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        let client = clients.get(ClientId(123)).unwrap();
+        assert_eq!(client.available(), Decimal::from(5));
+        assert_eq!(client.held(), Decimal::from(0));
+        assert_eq!(client.total(), Decimal::from(5));
+        assert_eq!(client.is_locked(), true);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+
+        // deposit 5.0
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Deposit,
+                client: ClientId(123),
+                tx: TransactionId(999),
+                amount: Some(5.into()),
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        // deposit 2.0
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Deposit,
+                client: ClientId(123),
+                tx: TransactionId(256),
+                amount: Some(2.into()),
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        // dispute
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Dispute,
+                client: ClientId(123),
+                tx: TransactionId(256),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        let client = clients.get(ClientId(123)).unwrap();
+        assert_eq!(client.available(), Decimal::from(5));
+        assert_eq!(client.held(), Decimal::from(2));
+        assert_eq!(client.total(), Decimal::from(7));
+        assert_eq!(client.is_locked(), false);
+
+        // resolve
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Resolve,
+                client: ClientId(123),
+                tx: TransactionId(256),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap();
+
+        let client = clients.get(ClientId(123)).unwrap();
+        assert_eq!(client.available(), Decimal::from(7));
+        assert_eq!(client.held(), Decimal::from(0));
+        assert_eq!(client.total(), Decimal::from(7));
+        assert_eq!(client.is_locked(), false);
+    }
+
+    #[test]
+    fn test_example() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 1.0
+            deposit, 2, 2, 2.0
+            deposit, 1, 3, 2.0
+            withdrawal, 1, 4, 1.5
+            withdrawal, 2, 5, 3.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,1.5,0,1.5,false
+            2,2,0,2,false
+        "};
+
+        let mut output = Vec::new();
+        process_csv(INPUT.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_precision() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 1000.2303
+            deposit, 1, 2, 2001.1533
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,3001.3836,0,3001.3836,false
+        "};
+
+        let mut output = Vec::new();
+        process_csv(INPUT.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_output_rounding() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 9.1333333
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,9.1333,0,9.1333,false
+        "};
+
+        let mut output = Vec::new();
+        process_csv(INPUT.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_negative_deposit() {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Deposit,
+                client: ClientId(123),
+                tx: TransactionId(999),
+                amount: Some((-1_i32).into()),
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_amount_too_large() {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Deposit,
+                client: ClientId(123),
+                tx: TransactionId(999),
+                amount: Some(Decimal::from(u64::MAX)),
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap_err();
+
+        assert!(clients.get(ClientId(123)).is_none());
+    }
+
+    #[test]
+    fn test_withdrawal_ids() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            deposit, 1, 2, 3.0
+            withdrawal, 1, 1, 1.0
+            dispute, 1, 2,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,4,0,4,false
+        "};
+
+        let mut output = Vec::new();
+        let stats = process_csv(INPUT.as_bytes(), &mut output).unwrap();
+        assert_eq!(stats.rejected, 3);
+        assert_eq!(stats.transactions, 2);
+        assert_eq!(stats.disputable_transactions, 1);
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_resubmission() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            dispute, 1, 1,
+            deposit, 1, 1, 5.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,-1,5,4,false
+        "};
+
+        let mut output = Vec::new();
+        let stats = process_csv(INPUT.as_bytes(), &mut output).unwrap();
+        assert_eq!(stats.rejected, 0);
+        assert_eq!(stats.duplicates, 3);
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_resubmission_precision() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            deposit, 1, 1, 5.00004
+            withdrawal, 1, 2, 1.00003
+            deposit, 1, 1, 5.0000
+            withdrawal, 1, 2, 1
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,4,0,4,false
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.duplicates, 2);
+        // amounts which only match once rounded aren't re-submissions
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (2, "transaction already exists".to_owned()),
+                (3, "transaction already exists".to_owned()),
+            ]
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+    }
+
+    #[test]
+    fn test_conflicting_resubmission() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            deposit, 1, 1, 6.0
+            deposit, 2, 1, 5.0
+            withdrawal, 1, 2, 2.0
+            withdrawal, 2, 2, 1.0
+            deposit, 1, 2, 1.0
+            withdrawal, 1, 1, 5.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,4,0,4,false
+        "};
+
+        let mut output = Vec::new();
+        let stats = process_csv(INPUT.as_bytes(), &mut output).unwrap();
+        assert_eq!(stats.rejected, 6);
+        assert_eq!(stats.duplicates, 0);
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_wide_ids() {
+        // 2^32 = 4294967296, 2^64 - 1 = 18446744073709551615
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 4294967296, 4294967296, 10.0
+            deposit, 18446744073709551615, 18446744073709551615, 5.0
+            withdrawal, 4294967296, 4294967297, 2.0
+            deposit, 4294967296, 4294967296, 10.0
+            withdrawal, 4294967296, 4294967297, 2.0
+            withdrawal, 4294967296, 4294967297, 3.0
+            deposit, 18446744073709551615, 4294967296, 10.0
+            dispute, 4294967296, 4294967296,
+            resolve, 4294967296, 4294967296,
+            dispute, 18446744073709551615, 18446744073709551615,
+            chargeback, 18446744073709551615, 18446744073709551615,
+            deposit, 1, 1, 1.0
+            dispute, 1, 4294967296,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            4294967296,8,0,8,false
+            18446744073709551615,0,0,0,true
+            1,1,0,1,false
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                events: Some(&mut events),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.duplicates, 2);
+
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (5, "transaction already exists".to_owned()),
+                (6, "transaction already exists".to_owned()),
+                (12, "transaction (Resolved) can't be disputed".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+
+        let replayed = Engine::replay(events.as_slice(), None).unwrap();
+        let mut replayed_output = Vec::new();
+        write_balances(replayed.clients(), &mut replayed_output).unwrap();
+        assert_eq!(String::from_utf8(replayed_output).unwrap(), OUTPUT);
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        // 1700000000 + 120 days = 1710368000, + 150 days = 1712960000
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 5.0, 1700000000
+            deposit, 1, 2, 3.0, 1700000000
+            deposit, 1, 3, 2.0,
+            deposit, 1, 4, 1.0, 1700000000
+            dispute, 1, 1, , 1710368000
+            dispute, 1, 2, , 1710368001
+            dispute, 1, 3, , 1800000000
+            resolve, 1, 1, , 1712960000
+            dispute, 1, 4, , 1700086400
+            chargeback, 1, 4, , 1702764801
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,8,3,11,false
+        "};
+
+        const DAY: u64 = 24 * 60 * 60;
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                dispute_policy: DisputePolicy {
+                    window: Some(Duration::from_secs(120 * DAY)),
+                    max_duration: Some(Duration::from_secs(30 * DAY)),
+                    on_expiry: None,
+                    unlock_on_reversal: false,
+                },
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 2);
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+
+        // only deposits (and rows without timestamps) up to this point
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                until_time: Some(1_700_000_000),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rows, 4);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("1,11,0,11,false\n"));
+
+        // rows with invalid timestamps are rejected, the rest is processed
+        let input = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 1.0, 0
+            deposit, 1, 2, 1.0, 4294967296
+            deposit, 1, 3, 1.0, 4294967295
+        "};
+        let (mut output, mut outcomes) = (Vec::new(), Vec::new());
+        let stats = process_csv_with(
+            input.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!((stats.rows, stats.rejected), (3, 2));
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (0, "timestamp should be > 0".to_owned()),
+                (1, "timestamp is out of range (after 2106)".to_owned()),
+            ]
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("1,1,0,1,false\n"), "{output}");
+    }
+
+    #[test]
+    fn test_dispute_by_another_client() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 10.0, 100
+            deposit, 2, 2, 5.0, 100
+            dispute, 2, 1, , 200
+            resolve, 2, 1, , 200
+            chargeback, 2, 1, , 200
+            dispute, 1, 1, , 200
+            deposit, 2, 3, 1.0, 300
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,10,0,10,false
+            2,6,0,6,false
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                dispute_policy: DisputePolicy {
+                    max_duration: Some(Duration::from_secs(1)),
+                    on_expiry: Some(DisputeExpiry::Resolve),
+                    ..DisputePolicy::default()
+                },
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.expired_disputes, 1);
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (2, "transaction belongs to another client".to_owned()),
+                (3, "transaction (Deposited) isn't under dispute".to_owned()),
+                (4, "transaction (Deposited) isn't under dispute".to_owned()),
+            ]
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+    }
+
+    #[test]
+    fn test_dispute_expiry() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 5.0, 100
+            deposit, 1, 2, 3.0, 100
+            dispute, 1, 1, , 200
+            dispute, 1, 2, , 250
+            deposit, 1, 3, 1.0, 300
+            deposit, 1, 4, 1.0, 400
+            resolve, 1, 2, , 400
+        "};
+
+        // after the first 4 rows
+        const OUTCOMES: &str = indoc! {r#"
+            {"row":4,"type":"chargeback","client":1,"tx":1,"outcome":"expired","status":"chargedback","available":"0","held":"3","total":"3","locked":true}
+            {"row":4,"type":"deposit","client":1,"tx":3,"outcome":"accepted","status":"deposited","available":"1","held":"3","total":"4","locked":true}
+            {"row":5,"type":"chargeback","client":1,"tx":2,"outcome":"expired","status":"chargedback","available":"1","held":"0","total":"1","locked":true}
+            {"row":5,"type":"deposit","client":1,"tx":4,"outcome":"accepted","status":"deposited","available":"2","held":"0","total":"2","locked":true}
+            {"row":6,"type":"resolve","client":1,"tx":2,"outcome":"rejected","error":"transaction (Chargedback) isn't under dispute","status":"chargedback","available":"2","held":"0","total":"2","locked":true}
+        "#};
+
+        let mut outcomes = Vec::new();
+        let mut events = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            io::sink(),
+            Options {
+                outcomes: Some(&mut outcomes),
+                events: Some(&mut events),
+                dispute_policy: DisputePolicy {
+                    max_duration: Some(Duration::from_secs(99)),
+                    on_expiry: Some(DisputeExpiry::Chargeback),
+                    ..DisputePolicy::default()
+                },
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.expired_disputes, 2);
+
+        let outcomes = String::from_utf8(outcomes).unwrap();
+        let outcomes: Vec<_> = outcomes.lines().skip(4).collect();
+        assert_eq!(outcomes.join("\n") + "\n", OUTCOMES);
+
+        // synthetic operations are in the event log, dated at the deadline
+        let events = String::from_utf8(events).unwrap();
+        assert!(
+            events.contains(r#"{"seq":5,"event":"charged_back","client":1,"tx":1,"amount":"5"}"#)
+        );
+        let engine = Engine::replay(events.as_bytes(), None).unwrap();
+        let mut output = Vec::new();
+        write_balances(engine.clients(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,2,0,2,true\n"
+        );
+    }
+
+    #[test]
+    fn test_overdraft_policy() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 4.0
+            deposit, 2, 3, 5.0
+            withdrawal, 2, 4, 2.0
+            dispute, 1, 1,
+            dispute, 2, 3,
+        "};
+
+        let process = |overdraft_policy| {
+            let mut output = Vec::new();
+            let stats = process_csv_with(
+                INPUT.as_bytes(),
+                &mut output,
+                Options {
+                    overdraft_policy,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            (stats.rejected, String::from_utf8(output).unwrap())
+        };
+
+        let (rejected, output) = process(OverdraftPolicy::Allow);
+        assert_eq!(rejected, 0);
+        assert_eq!(
+            output,
+            indoc! {"
+                client,available,held,total,locked
+                1,-4,5,1,false
+                2,-2,5,3,false
+            "}
+        );
+
+        let (rejected, output) = process(OverdraftPolicy::Reject);
+        assert_eq!(rejected, 2);
+        assert_eq!(
+            output,
+            indoc! {"
+                client,available,held,total,locked,exposure
+                1,1,0,1,false,0
+                2,3,0,3,false,0
+            "}
+        );
+
+        let (rejected, output) = process(OverdraftPolicy::Limit {
+            default: Decimal::from(3),
+            clients: [(ClientId(1), Decimal::from(4))].into_iter().collect(),
+        });
+        assert_eq!(rejected, 0);
+        assert_eq!(
+            output,
+            indoc! {"
+                client,available,held,total,locked,exposure
+                1,-4,5,1,false,4
+                2,-2,5,3,false,2
+            "}
+        );
+
+        let (rejected, _) = process(OverdraftPolicy::Limit {
+            default: Decimal::from(3),
+            clients: [(ClientId(1), Decimal::new(39999, 4))]
+                .into_iter()
+                .collect(),
+        });
+        assert_eq!(rejected, 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        // day 1 starts at 86400
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 100.0, 86400
+            deposit, 1, 2, 100.01, 86401
+            withdrawal, 1, 3, 30.0, 86402
+            withdrawal, 1, 4, 25.0, 86403
+            withdrawal, 1, 5, 20.0, 86500
+            withdrawal, 1, 6, 20.0, 172800
+            deposit, 1, 7, 1.0, 172801
+            deposit, 1, 8, 1.0, 172802
+            deposit, 1, 9, 1.0, 172861
+            deposit, 2, 10, 500.0, 172900
+            withdrawal, 2, 11, 250.0,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,57,0,57,false
+            2,250,0,250,false
+        "};
+
+        let limits = LimitsConfig::from_reader(
+            indoc! {r#"
+                {
+                    "default": {
+                        "max_deposit": "100",
+                        "max_withdrawal": "25",
+                        "max_daily_withdrawal": "40",
+                        "max_operations": { "count": 2, "window_secs": 60 }
+                    },
+                    "clients": { "2": { "max_deposit": "1000", "max_withdrawal": "1000" } }
+                }
+            "#}
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                limits,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 4);
+
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (1, "deposit amount exceeds limit".to_owned()),
+                (2, "withdrawal amount exceeds limit".to_owned()),
+                (4, "daily withdrawal limit exceeded".to_owned()),
+                (7, "too many operations".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_withdrawal_holds() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 100.0
+            withdrawal, 1, 2, 60.0
+            withdrawal, 1, 3, 50.0
+            approve, 2, 2,
+            approve, 1, 2,
+            deposit, 2, 4, 100.0
+            withdrawal, 2, 5, 80.0
+            decline, 2, 5,
+            approve, 2, 5,
+            approve, 1, 1,
+            withdrawal, 1, 6, 10.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,reserved,total,locked
+            1,30,0,0,30,false
+            2,100,0,0,100,false
+        "};
+
+        let rules = read_rules(
+            r#"[{ "name": "large", "amount_above": "50", "action": "hold" }]"#.as_bytes(),
+        )
+        .unwrap();
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                rules: rules
+                    .into_iter()
+                    .map(|rule| Box::new(rule) as Box<dyn Rule>)
+                    .collect(),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.held, 2);
+        assert_eq!(stats.flagged, 4);
+
+        let outcomes: Vec<_> = String::from_utf8(outcomes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|outcome| {
+                format!(
+                    "{} {} {}",
+                    outcome["outcome"].as_str().unwrap(),
+                    outcome["status"],
+                    outcome["error"].as_str().unwrap_or("-"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                r#"accepted "deposited" -"#,
+                r#"held "pending" -"#,
+                "rejected null not enough funds",
+                r#"rejected "pending" transaction belongs to another client"#,
+                r#"accepted "withdrawn" -"#,
+                r#"accepted "deposited" -"#,
+                r#"held "pending" -"#,
+                r#"accepted "declined" -"#,
+                r#"rejected "declined" transaction (Declined) isn't pending review"#,
+                r#"rejected "deposited" transaction (Deposited) isn't pending review"#,
+                r#"accepted "withdrawn" -"#,
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_authorizations() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 100.0
+            authorize, 1, 2, 60.0
+            authorize, 1, 2, 60.0
+            authorize, 1, 3, 50.0
+            capture, 1, 2, 70.0
+            capture, 2, 2,
+            capture, 1, 2, 45.5
+            void, 1, 2,
+            authorize, 1, 4, 30.0
+            void, 1, 4,
+            capture, 1, 4,
+            authorize, 1, 5, 10.0
+            capture, 1, 1,
+            withdrawal, 1, 2, 60.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,reserved,total,locked
+            1,44.5,0,10,54.5,false
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.duplicates, 1);
+
+        let outcomes: Vec<_> = String::from_utf8(outcomes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|outcome| {
+                format!(
+                    "{} {} {}",
+                    outcome["outcome"].as_str().unwrap(),
+                    outcome["status"],
+                    outcome["error"].as_str().unwrap_or("-"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                r#"accepted "deposited" -"#,
+                r#"accepted "authorized" -"#,
+                r#"duplicate "authorized" -"#,
+                "rejected null not enough funds",
+                r#"rejected "authorized" capture amount exceeds authorized amount"#,
+                r#"rejected "authorized" transaction belongs to another client"#,
+                r#"accepted "captured" -"#,
+                r#"rejected "captured" transaction (Captured) isn't authorized"#,
+                r#"accepted "authorized" -"#,
+                r#"accepted "voided" -"#,
+                r#"rejected "voided" transaction (Voided) isn't authorized"#,
+                r#"accepted "authorized" -"#,
+                r#"rejected "deposited" transaction (Deposited) isn't authorized"#,
+                r#"rejected "captured" transaction already exists"#,
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_transfers() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, to
+            deposit, 1, 1, 100.0,
+            transfer, 1, 2, 30.0, 2
+            transfer, 1, 2, 30.0, 2
+            transfer, 1, 2, 30.0, 3
+            transfer, 1, 3, 80.0, 2
+            transfer, 1, 4, 10.0, 1
+            transfer, 1, 5, 10.0,
+            withdrawal, 2, 6, 10.0,
+            dispute, 3, 2, ,
+            dispute, 1, 2, ,
+            chargeback, 2, 2, ,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,100,0,100,false
+            2,-10,0,-10,true
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                events: Some(&mut events),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.duplicates, 1);
+
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (3, "transaction already exists".to_owned()),
+                (4, "not enough funds".to_owned()),
+                (5, "transfer to the same client".to_owned()),
+                (6, "no destination for transfer".to_owned()),
+                (8, "transaction belongs to another client".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+
+        let replayed = Engine::replay(events.as_slice(), None).unwrap();
+        let mut output = Vec::new();
+        write_balances(replayed.clients(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            indoc! {"
+                client,available,held,total,locked
+                1,100,0,100,false
+                2,-10,0,-10,true
+            "}
+        );
+    }
+
+    #[test]
+    fn test_fees() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 100.0
+            withdrawal, 1, 2, 10.0
+            withdrawal, 1, 3, 88.5
+            withdrawal, 1, 4, 50.0
+            deposit, 2, 5, 20.0
+            withdrawal, 2, 6, 10.0
+            dispute, 1, 1,
+            chargeback, 1, 1,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked,fees
+            1,-77,0,-77,true,17
+            2,10,0,10,false,0
+        "};
+
+        let fees = FeeSchedule::from_reader(
+            indoc! {r#"
+                {
+                    "default": {
+                        "withdrawal": { "flat": "0.5", "percent": "1", "min": "1" },
+                        "chargeback": { "flat": "15" }
+                    },
+                    "tiers": { "gold": { "withdrawal": { "flat": "0" } } },
+                    "clients": { "2": "gold" }
+                }
+            "#}
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                events: Some(&mut events),
+                fees,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.fee_revenue, Decimal::from(17));
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+
+        let fee_events: Vec<_> = read_events(events.as_slice())
+            .map(|record| record.unwrap().event)
+            .filter(|event| matches!(event, Event::FeeCharged { .. }))
+            .collect();
+        assert_eq!(
+            fee_events,
+            [
+                Event::FeeCharged {
+                    client: ClientId(1),
+                    tx: TransactionId(2),
+                    amount: Decimal::ONE,
+                },
+                Event::FeeCharged {
+                    client: ClientId(1),
+                    tx: TransactionId(4),
+                    amount: Decimal::ONE,
+                },
+                Event::FeeCharged {
+                    client: ClientId(1),
+                    tx: TransactionId(1),
+                    amount: Decimal::from(15),
+                },
+            ]
+        );
+
+        let replayed = Engine::replay(events.as_slice(), None).unwrap();
+        let mut output = Vec::new();
+        write_balances(replayed.clients(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+    }
+
+    #[test]
+    fn test_reversal() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, to
+            deposit, 1, 1, 100.0,
+            deposit, 1, 2, 20.0,
+            withdrawal, 1, 3, 10.0,
+            dispute, 1, 1, ,
+            chargeback, 1, 1, ,
+            reversal, 1, 2, ,
+            reversal, 1, 1, ,
+            reversal, 1, 1, ,
+            dispute, 1, 1, ,
+            transfer, 1, 4, 30.0, 2
+            dispute, 2, 4, ,
+            chargeback, 2, 4, ,
+            reversal, 1, 4, ,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked,fees
+            1,75,0,75,false,5
+            2,25,0,25,false,5
+        "};
+
+        let fees = FeeSchedule::from_reader(
+            r#"{ "default": { "chargeback": { "flat": "5" } } }"#.as_bytes(),
+        )
+        .unwrap();
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                dispute_policy: DisputePolicy {
+                    unlock_on_reversal: true,
+                    ..DisputePolicy::default()
+                },
+                fees,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (5, "transaction (Deposited) isn't charged back".to_owned()),
+                (7, "transaction (Reversed) isn't charged back".to_owned()),
+                (8, "transaction (Reversed) can't be disputed".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_admin_operations() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, reason
+            deposit, 1, 1, 10.0,
+            deposit, 2, 2, 5.0,
+            dispute, 1, 1, ,
+            chargeback, 1, 1, ,
+            lock, 1, 0, , fraud
+            unlock, 1, 0, , arbitration won
+            unlock, 1, 0, ,
+            lock, 2, 0, ,
+            lock, 2, 0, , suspicious activity
+            close, 2, 0, ,
+            close, 1, 0, , customer request
+            deposit, 1, 3, 1.0,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked,status,reason
+            1,0,0,0,false,closed,customer request
+            2,5,0,5,true,frozen,suspicious activity
+        "};
+
+        let stats = process_csv(INPUT.as_bytes(), io::sink()).unwrap();
+        assert_eq!(stats.rejected, 7);
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                admin: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (4, "account is already locked".to_owned()),
+                (6, "account isn't locked".to_owned()),
+                (7, "no reason for lock".to_owned()),
+                (9, "account balance isn't zero".to_owned()),
+                (11, "account is closed".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_frozen_account() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, to, reason
+            deposit, 1, 1, 10.0, ,
+            withdrawal, 1, 2, 8.5, ,
+            lock, 1, 0, , , review
+            withdrawal, 1, 3, 0.1, ,
+            transfer, 1, 4, 0.1, 2,
+            authorize, 1, 5, 0.1, ,
+            deposit, 1, 6, 0.2, ,
+            deposit, 1, 7, 2.0, ,
+            deposit, 2, 8, 5.0, ,
+            transfer, 2, 9, 1.0, 1,
+            unlock, 1, 0, , , cleared
+            withdrawal, 1, 10, 1.0, ,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked,fees,status,reason
+            1,1.5,0,1.5,false,2,open,cleared
+            2,3,0,3,false,1,open,
+        "};
+
+        let fees =
+            FeeSchedule::from_reader(r#"{ "default": { "deposit": { "flat": "1" } } }"#.as_bytes())
+                .unwrap();
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                admin: true,
+                fees,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        // debits are rejected, credits aren't unless their fee would overdraw the account
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (3, "account is frozen".to_owned()),
+                (4, "account is frozen".to_owned()),
+                (5, "account is frozen".to_owned()),
+                (6, "not enough funds for fee (account is frozen)".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+    }
+
+    #[test]
+    fn test_frozen_reservations() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, reason
+            deposit, 1, 1, 100.0,
+            withdrawal, 1, 2, 60.0,
+            authorize, 1, 3, 10.0,
+            authorize, 1, 4, 10.0,
+            lock, 1, 0, , review
+            approve, 1, 2, ,
+            capture, 1, 3, ,
+            void, 1, 4, ,
+            unlock, 1, 0, , cleared
+            approve, 1, 2, ,
+            capture, 1, 3, 5.0,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,reserved,total,locked,status,reason
+            1,35,0,0,35,false,open,cleared
+        "};
+
+        let rules = read_rules(
+            r#"[{ "name": "large", "amount_above": "50", "action": "hold" }]"#.as_bytes(),
+        )
+        .unwrap();
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                admin: true,
+                rules: rules
+                    .into_iter()
+                    .map(|rule| Box::new(rule) as Box<dyn Rule>)
+                    .collect(),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        // reserved funds aren't debited from a frozen account, but can be released
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (5, "account is frozen".to_owned()),
+                (6, "account is frozen".to_owned()),
+            ]
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+    }
+
+    #[test]
+    fn test_strict_accounts() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 2, 5.0
+            open, 2, 0,
+            deposit, 2, 2, 5.0
+            deposit, 3, 3, 5.0
+            open, 3, 0,
+            open, 3, 0,
+            withdrawal, 4, 4, 1.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked,status,reason
+            1,5,0,5,false,open,
+            2,5,0,5,false,open,
+            3,0,0,0,false,open,
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                strict_accounts: true,
+                accounts: read_accounts("client,status\n1,\n2,pending\n".as_bytes())
+                    .collect::<Result<_, _>>()
+                    .unwrap(),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 4);
+
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (1, "account isn't open".to_owned()),
+                (4, "account does not exist".to_owned()),
+                (6, "account already exists".to_owned()),
+                (7, "account does not exist".to_owned()),
+            ]
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+
+        // rejected operations don't create accounts
+        let mut output = Vec::new();
+        process_csv(INPUT.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            indoc! {"
+                client,available,held,total,locked
+                1,5,0,5,false
+                2,5,0,5,false
+                3,5,0,5,false
+            "}
+        );
+    }
+
+    #[test]
+    fn test_opening_balances() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, reason
+            deposit, 1, 1, 10.0,
+            deposit, 2, 2, 5.0,
+            dispute, 2, 2, ,
+            deposit, 3, 3, 1.0,
+            withdrawal, 3, 4, 1.0,
+            close, 3, 0, , done
+            lock, 1, 0, , review
+        "};
+
+        const NEXT_INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 2, 5, 1.0
+            deposit, 1, 6, 1.0
+            deposit, 3, 7, 1.0
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked,status,reason
+            1,11,0,11,true,frozen,review
+            2,1,5,6,false,open,
+            3,0,0,0,false,closed,done
+        "};
+
+        let accounts = |input: &str| {
+            read_accounts(input.as_bytes())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        let mut output = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                admin: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        // chained: output of the first run is the accounts file of the next one
+        let mut next_output = Vec::new();
+        let stats = process_csv_with(
+            NEXT_INPUT.as_bytes(),
+            &mut next_output,
+            Options {
+                accounts: accounts(&output),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(String::from_utf8(next_output).unwrap(), OUTPUT);
+
+        for (input, error) in [
+            (
+                "client,available,held,total,locked\n1,1,0,2,false\n",
+                "account 1: total isn't available + held + reserved",
+            ),
+            (
+                "client,available,held\n1,1,-1\n",
+                "account 1: held amount is negative",
+            ),
+            (
+                "client,available,status\n1,1,closed\n",
+                "account 1: closed account balance isn't zero",
+            ),
+            ("client\n1\n1\n", "account 1 already exists"),
+        ] {
+            let err = process_csv_with(
+                NEXT_INPUT.as_bytes(),
+                io::sink(),
+                Options {
+                    accounts: accounts(input),
+                    ..Options::default()
+                },
+            )
+            .unwrap_err();
+            assert_eq!(err.to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_opening_balances_round_trip() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 10.0
+            authorize, 1, 2, 4.0
+            deposit, 2, 3, 5.0
+            deposit, 2, 4, 2.0
+            dispute, 2, 4,
+            deposit, 3, 5, 3.0
+            dispute, 3, 5,
+            chargeback, 3, 5,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,reserved,total,locked
+            1,6,0,4,10,false
+            2,5,2,0,7,false
+            3,0,0,0,0,true
+        "};
+
+        let mut output = Vec::new();
+        process_csv_with(INPUT.as_bytes(), &mut output, Options::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, OUTPUT);
+
+        let mut reopened = Vec::new();
+        process_csv_with(
+            "type, client, tx, amount\n".as_bytes(),
+            &mut reopened,
+            Options {
+                accounts: read_accounts(output.as_bytes())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(reopened).unwrap(), OUTPUT);
+    }
+
+    #[test]
+    fn test_client_tx_scope() {
+        // 1700000000 + 1 day = 1700086400
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 5.0,
+            deposit, 2, 1, 3.0,
+            dispute, 2, 1, , 1700000000
+            withdrawal, 1, 2, 1.0,
+            withdrawal, 2, 2, 1.0,
+            deposit, 1, 1, 5.0,
+            deposit, 3, 1, 1.0, 1700200000
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,4,0,4,false
+            2,3,0,3,false
+            3,1,0,1,false
+        "};
+
+        // with global ids, client 2 can't dispute transaction 1 of client 1
+        let stats = process_csv(INPUT.as_bytes(), io::sink()).unwrap();
+        assert_eq!(stats.rejected, 4);
+
+        let mut outcomes = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                dispute_policy: DisputePolicy {
+                    max_duration: Some(Duration::from_secs(24 * 60 * 60)),
+                    on_expiry: Some(DisputeExpiry::Resolve),
+                    ..DisputePolicy::default()
+                },
+                tx_scope: TxScope::Client,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.expired_disputes, 1);
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+
+        let outcomes: Vec<_> = String::from_utf8(outcomes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|outcome| {
+                format!(
+                    "{} {} {} {} {}",
+                    outcome["type"].as_str().unwrap(),
+                    outcome["client"],
+                    outcome["tx"],
+                    outcome["outcome"].as_str().unwrap(),
+                    outcome["status"],
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                r#"deposit 1 1 accepted "deposited""#,
+                r#"deposit 2 1 accepted "deposited""#,
+                r#"dispute 2 1 accepted "disputed""#,
+                r#"withdrawal 1 2 accepted "withdrawn""#,
+                "withdrawal 2 2 rejected null",
+                r#"deposit 1 1 duplicate "deposited""#,
+                r#"resolve 2 1 expired "resolved""#,
+                r#"deposit 3 1 accepted "deposited""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_scope_transfer() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, to
+            deposit, 1, 1, 10.0,
+            deposit, 2, 1, 5.0,
+            deposit, 2, 7, 1.0,
+            transfer, 1, 2, 3.0, 2
+            transfer, 1, 7, 1.0, 2
+            deposit, 2, 2, 1.0,
+            dispute, 2, 2, ,
+            chargeback, 1, 2, ,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,10,0,10,false
+            2,6,0,6,true
+        "};
+
+        let mut outcomes = Vec::new();
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                outcomes: Some(&mut outcomes),
+                events: Some(&mut events),
+                tx_scope: TxScope::Client,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        // the transfer's id is taken in the scope of both clients
+        assert_eq!(
+            errors(&outcomes),
+            [
+                (4, "transaction already exists".to_owned()),
+                (5, "transaction already exists".to_owned()),
+            ]
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+
+        let replayed = Engine::replay(events.as_slice(), None).unwrap();
+        for client in [1, 2] {
+            let dispute = Operation {
+                op_type: OperationType::Dispute,
+                client: ClientId(client),
+                tx: TransactionId(2),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            };
+            assert_eq!(
+                replayed.transaction_status(&dispute),
+                Some(TransactionStatus::Chargedback)
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejected_scoped_id() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 2, 1, 1.0
+            deposit, 3, 1, 2.0
+            deposit, 2, 1, 3.0
+            dispute, 2, 1,
+        "};
+
+        const OUTPUT: &str = indoc! {"
+            client,available,held,total,locked
+            1,5,0,5,false
+            3,2,0,2,false
+            2,0,3,3,false
+        "};
+
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            &mut output,
+            Options {
+                events: Some(&mut events),
+                tx_scope: TxScope::Client,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(String::from_utf8(output).unwrap(), OUTPUT);
+
+        // internal ids are only assigned to accepted transactions
+        let deposits: Vec<_> = read_events(events.as_slice())
+            .map(Result::unwrap)
+            .filter_map(|record| match record.event {
+                Event::Deposited { client, tx, .. } => Some((client.0, tx.0)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deposits, [(1, 0), (3, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_source_tx_scope() {
+        const FIRST: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+        "};
+
+        const SECOND: &str = indoc! {"
+            type, client, tx, amount, source
+            deposit, 1, 1, 2.0,
+            dispute, 1, 1, , first
+            dispute, 1, 1, ,
+            dispute, 1, 2, , first
+            resolve, 1, 1, , third
+        "};
+
+        fn options<'a>() -> Options<'a> {
+            Options {
+                tx_scope: TxScope::Source,
+                ..Options::default()
+            }
+        }
+        let mut events = Vec::new();
+        let (engine, stats) = process_inputs_into_engine(
+            [
+                (Some("first".to_owned()), FIRST.as_bytes()),
+                (Some("second".to_owned()), SECOND.as_bytes()),
+            ],
+            Options {
+                events: Some(&mut events),
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.rows, 6);
+        assert_eq!(stats.rejected, 2);
+        let client = engine.clients().get(ClientId(1)).unwrap();
+        assert_eq!(client.available(), Decimal::ZERO);
+        assert_eq!(client.held(), Decimal::from(7));
+
+        // the event log maps internal ids to scoped ones, so replay knows them too
+        let scoped: Vec<_> = read_events(events.as_slice())
+            .map(Result::unwrap)
+            .filter_map(|record| match record.event {
+                Event::TransactionScoped {
+                    tx,
+                    scoped_tx,
+                    source,
+                    ..
+                } => Some((tx.0, scoped_tx.0, source?)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            scoped,
+            [(0, 1, "first".to_owned()), (1, 1, "second".to_owned())]
+        );
+        let replayed = Engine::replay(events.as_slice(), None).unwrap();
+        for source in ["first", "second"] {
+            let dispute = Operation {
+                op_type: OperationType::Dispute,
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: Some(source.to_owned()),
+            };
+            assert_eq!(
+                replayed.transaction_status(&dispute),
+                Some(TransactionStatus::Disputed)
+            );
+        }
+        assert!(diff_states(&engine, &replayed).summary.is_empty());
+
+        // no source for transaction
+        let (engine, stats) = process_csv_into_engine(FIRST.as_bytes(), options()).unwrap();
+        assert_eq!(stats.rejected, 1);
+        assert!(engine.clients().get(ClientId(1)).is_none());
+    }
+
+    #[test]
+    fn test_outcomes() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            withdrawal, 1, 2, 1.0
+            withdrawal, 1, 3, 10.0
+            dispute, 1, 1,
+        "};
+
+        const OUTCOMES: &str = indoc! {r#"
+            {"row":0,"type":"deposit","client":1,"tx":1,"outcome":"accepted","status":"deposited","available":"5","held":"0","total":"5","locked":false}
+            {"row":1,"type":"withdrawal","client":1,"tx":2,"outcome":"accepted","status":"withdrawn","available":"4","held":"0","total":"4","locked":false}
+            {"row":2,"type":"withdrawal","client":1,"tx":2,"outcome":"duplicate","status":"withdrawn","available":"4","held":"0","total":"4","locked":false}
+            {"row":3,"type":"withdrawal","client":1,"tx":3,"outcome":"rejected","error":"not enough funds","status":null,"available":"4","held":"0","total":"4","locked":false}
+            {"row":4,"type":"dispute","client":1,"tx":1,"outcome":"accepted","status":"disputed","available":"-1","held":"5","total":"4","locked":false}
+        "#};
+
+        let mut outcomes = Vec::new();
+        process_csv_with(
+            INPUT.as_bytes(),
+            io::sink(),
+            Options {
+                outcomes: Some(&mut outcomes),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        let outcomes = String::from_utf8(outcomes).unwrap();
+        assert_eq!(outcomes, OUTCOMES);
+    }
+
+    #[test]
+    fn test_invalid_dispute() {
+        let mut clients = ClientDb::default();
+        let mut transactions = TransactionDb::default();
+        process_operation(
+            &mut clients,
+            &mut transactions,
+            &Operation {
+                op_type: OperationType::Dispute,
+                client: ClientId(123),
+                tx: TransactionId(999),
+                amount: None,
+                timestamp: None,
+                to: None,
+                reason: None,
+                source: None,
+            },
+        )
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::VecDeque, io};
+
+use anyhow::{ensure, Context};
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+
+use crate::{ClientId, Operation, OperationType};
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Risk limits of a client, all optional.
+///
+/// note: Velocity limits (daily withdrawals, operation rate) only apply to operations with
+/// timestamps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Largest single deposit.
+    pub max_deposit: Option<Decimal>,
+    /// Largest single withdrawal.
+    pub max_withdrawal: Option<Decimal>,
+    /// Largest total of withdrawals in a day (UTC).
+    pub max_daily_withdrawal: Option<Decimal>,
+    /// Largest number of accepted operations in a time window.
+    pub max_operations: Option<OperationRate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OperationRate {
+    pub count: u32,
+    pub window_secs: u32,
+}
+
+impl Limits {
+    /// Limits set here, falling back to `defaults` for the rest.
+    fn or(&self, defaults: &Self) -> Self {
+        Self {
+            max_deposit: self.max_deposit.or(defaults.max_deposit),
+            max_withdrawal: self.max_withdrawal.or(defaults.max_withdrawal),
+            max_daily_withdrawal: self.max_daily_withdrawal.or(defaults.max_daily_withdrawal),
+            max_operations: self.max_operations.or(defaults.max_operations),
+        }
+    }
+}
+
+/// Limits configuration: global defaults, overridden per client (field by field).
+///
+/// ```json
+/// {
+///   "default": { "max_withdrawal": "1000", "max_operations": { "count": 10, "window_secs": 60 } },
+///   "clients": { "42": { "max_withdrawal": "5000" } }
+/// }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    #[serde(default)]
+    pub default: Limits,
+    #[serde(default)]
+    pub clients: FxHashMap<ClientId, Limits>,
+}
+
+impl LimitsConfig {
+    /// Reads configuration from JSON.
+    pub fn from_reader<R: io::Read>(reader: R) -> anyhow::Result<Self> {
+        serde_json::from_reader(io::BufReader::new(reader)).context("invalid limits config")
+    }
+
+    pub fn for_client(&self, client_id: ClientId) -> Limits {
+        match self.clients.get(&client_id) {
+            Some(limits) => limits.or(&self.default),
+            None => self.default,
+        }
+    }
+}
+
+/// Recent activity of a client, for velocity limits.
+#[derive(Debug, Default, Clone)]
+struct Activity {
+    /// Day (since Unix epoch) `withdrawn` is for.
+    day: u32,
+    withdrawn: Decimal,
+    /// Timestamps of recent accepted operations (oldest first).
+    operations: VecDeque<u32>,
+}
+
+/// Limits together with client activity they're checked against.
+///
+/// note: Activity isn't part of the event log, it only affects which operations are accepted.
+#[derive(Debug, Default)]
+pub(crate) struct RiskControls {
+    config: LimitsConfig,
+    activity: FxHashMap<ClientId, Activity>,
+}
+
+impl RiskControls {
+    pub(crate) fn new(config: LimitsConfig) -> Self {
+        Self {
+            config,
+            activity: FxHashMap::default(),
+        }
+    }
+
+    pub(crate) fn limits(&self, client_id: ClientId) -> Limits {
+        self.config.for_client(client_id)
+    }
+
+    /// Total withdrawn by a client on the day of `timestamp`.
+    fn withdrawn_on(&self, client_id: ClientId, timestamp: u32) -> Decimal {
+        self.activity
+            .get(&client_id)
+            .filter(|activity| activity.day == timestamp / SECONDS_PER_DAY)
+            .map_or(Decimal::ZERO, |activity| activity.withdrawn)
+    }
+
+    /// Checks daily withdrawal limit for a withdrawal of `amount`.
+    pub(crate) fn check_withdrawal(
+        &self,
+        limits: &Limits,
+        operation: &Operation,
+        amount: Decimal,
+    ) -> anyhow::Result<()> {
+        let (Some(max), Some(timestamp)) = (limits.max_daily_withdrawal, operation.timestamp)
+        else {
+            return Ok(());
+        };
+        let total = self
+            .withdrawn_on(operation.client, timestamp)
+            .checked_add(amount);
+        ensure!(
+            total.is_some_and(|total| total <= max),
+            "daily withdrawal limit exceeded"
+        );
+        Ok(())
+    }
+
+    /// Checks operation rate limit.
+    pub(crate) fn check_rate(&self, limits: &Limits, operation: &Operation) -> anyhow::Result<()> {
+        let (Some(rate), Some(timestamp)) = (limits.max_operations, operation.timestamp) else {
+            return Ok(());
+        };
+        let window_start = timestamp.saturating_sub(rate.window_secs);
+        let recent = self.activity.get(&operation.client).map_or(0, |activity| {
+            let operations = &activity.operations;
+            let start = operations.partition_point(|time| *time <= window_start);
+            operations.len().saturating_sub(start)
+        });
+        ensure!(recent < usize::try_from(rate.count)?, "too many operations");
+        Ok(())
+    }
+
+    /// Records an accepted operation (`amount` is the withdrawn amount for withdrawals,
+    /// authorizations and transfers).
+    pub(crate) fn record(&mut self, operation: &Operation, amount: Option<Decimal>) {
+        let Some(timestamp) = operation.timestamp else {
+            return;
+        };
+        let limits = self.config.for_client(operation.client);
+        if limits.max_daily_withdrawal.is_none() && limits.max_operations.is_none() {
+            return;
+        }
+        let activity = self.activity.entry(operation.client).or_default();
+
+        if let (
+            OperationType::Withdrawal | OperationType::Authorize | OperationType::Transfer,
+            Some(amount),
+        ) = (operation.op_type, amount)
+        {
+            let day = timestamp / SECONDS_PER_DAY;
+            if activity.day != day {
+                activity.day = day;
+                activity.withdrawn = Decimal::ZERO;
+            }
+            activity.withdrawn = activity.withdrawn.saturating_add(amount);
+        }
+
+        if let Some(rate) = limits.max_operations {
+            let window_start = timestamp.saturating_sub(rate.window_secs);
+            while activity
+                .operations
+                .front()
+                .is_some_and(|time| *time <= window_start)
+            {
+                activity.operations.pop_front();
+            }
+            activity.operations.push_back(timestamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = LimitsConfig::from_reader(
+            r#"{
+                "default": {
+                    "max_withdrawal": "1000",
+                    "max_operations": { "count": 10, "window_secs": 60 }
+                },
+                "clients": { "42": { "max_withdrawal": "5000", "max_deposit": "1.5" } }
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let limits = config.for_client(ClientId(42));
+        assert_eq!(limits.max_withdrawal, Some(Decimal::from(5000)));
+        assert_eq!(limits.max_deposit, Some(Decimal::new(15, 1)));
+        assert_eq!(limits.max_daily_withdrawal, None);
+        assert_eq!(limits.max_operations, config.default.max_operations);
+        assert_eq!(config.for_client(ClientId(1)), config.default);
+
+        assert!(
+            LimitsConfig::from_reader(r#"{"default": {"max_deposits": "1"}}"#.as_bytes()).is_err()
+        );
+    }
+}
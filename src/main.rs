@@ -1,383 +1,427 @@
-use std::{fs::File, io};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
-use anyhow::{bail, ensure, Context};
-use rust_decimal::Decimal;
+use anyhow::{bail, Context};
 
-use crate::{
-    client::{ClientDb, ClientId},
-    transaction::{TransactionDb, TransactionId},
+use payments::{
+    diff_states, process_inputs_into_engine, read_accounts, read_rules, reconcile, write_balances,
+    write_differences, ClientId, DisputeExpiry, DisputePolicy, Engine, Fee, FeeSchedule,
+    LimitsConfig, OperationType, Options, OverdraftPolicy, Rule, Stats, TxScope,
 };
+use rust_decimal::Decimal;
 
-mod client;
-mod transaction;
+enum Command {
+    /// Process transactions file and output client balances.
+    Process(Box<ProcessArgs>),
+    /// Rebuild client balances from an event log.
+    Replay(ReplayArgs),
+    /// Process transactions file and compare client balances with expected ones.
+    Reconcile(Box<ProcessArgs>),
+    /// Compare engine states rebuilt from two event logs.
+    Diff(DiffArgs),
+}
 
-// note: Ideally we don't want "dispute/resolve/chargeback" to have `amount` field. And
-// we also want it to be non-optional for "deposit/withdrawal". This can be done with an
-// enum, howevever I couldn't get it to work quickly with csv deserialiazer. Another option
-// is to just have this type as serialize/deserialize intermediate type and build an enum
-// from it (as fallible operation).
-#[derive(Debug, serde::Deserialize)]
-struct Operation {
-    #[serde(rename = "type")]
-    op_type: OperationType,
-    client: ClientId,
-    tx: TransactionId,
-    amount: Option<Decimal>,
+struct ProcessArgs {
+    /// Input files, processed in order (each one is the source of its rows).
+    input_paths: Vec<String>,
+    /// Expected balances (CSV) file, reconcile only.
+    expected_path: Option<String>,
+    /// Print throughput and peak memory to stderr after processing.
+    stats: bool,
+    /// Write per-operation outcomes (JSON Lines) to this file.
+    outcomes_path: Option<String>,
+    /// Write the event log (JSON Lines) to this file.
+    events_path: Option<String>,
+    /// Only process rows up to (and including) this index.
+    as_of_row: Option<u64>,
+    /// Only process rows up to this Unix time (seconds).
+    as_of_time: Option<u32>,
+    dispute_policy: DisputePolicy,
+    overdraft_policy: OverdraftPolicy,
+    /// Limits config (JSON) file.
+    limits_path: Option<String>,
+    /// Rules (JSON) file.
+    rules_path: Option<String>,
+    /// Fee schedule (JSON) file.
+    fees_path: Option<String>,
+    /// Flat chargeback fee, overrides the default one from the fee schedule.
+    chargeback_fee: Option<Decimal>,
+    /// Accept administrative operations.
+    admin: bool,
+    /// Require accounts to be opened before they're used.
+    strict_accounts: bool,
+    /// Accounts (CSV) file, opened before processing.
+    accounts_path: Option<String>,
+    tx_scope: TxScope,
 }
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum OperationType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
+/// Process args, with input filenames still to be assigned.
+struct ParsedProcessArgs {
+    args: ProcessArgs,
+    positional: Vec<String>,
 }
 
-fn process_operation(
-    clients: &mut ClientDb,
-    transactions: &mut TransactionDb,
-    operation: &Operation,
-) -> anyhow::Result<()> {
-    let client = clients.get_mut(operation.client);
-    match operation.op_type {
-        OperationType::Deposit => {
-            let amount = operation.amount.context("no amount for deposit")?;
-            let deposit = transactions.deposit(operation.tx, amount)?;
-            client.deposit(deposit)?;
-        }
-        OperationType::Withdrawal => {
-            let amount = operation.amount.context("no amount for withdrawal")?;
-            let authorized_withdrawal = client.authorize_withdrawal(operation.tx, amount)?;
-            let withdrawal = transactions.withdraw(authorized_withdrawal)?;
-            client.withdraw(withdrawal)?;
-        }
-        OperationType::Dispute => {
-            ensure!(
-                operation.amount.is_none(),
-                "amount isn't expected for dispute"
-            );
-            let disputed = transactions.dispute(operation.tx)?;
-            client.dispute_deposit(disputed)?;
-        }
-        OperationType::Resolve => {
-            ensure!(
-                operation.amount.is_none(),
-                "amount isn't expected for resolve"
-            );
-            let resolved = transactions.resolve(operation.tx)?;
-            client.resolve_dispute(resolved)?;
-        }
-        OperationType::Chargeback => {
-            ensure!(
-                operation.amount.is_none(),
-                "amount isn't expected for chargeback"
-            );
-            let chargedback = transactions.chargeback(operation.tx)?;
-            client.chargeback(chargedback)?;
-        }
-    }
-    Ok(())
+struct DiffArgs {
+    before_path: String,
+    after_path: String,
+}
+
+struct ReplayArgs {
+    log_path: String,
+    /// Only apply events up to (and including) this sequence number.
+    until_seq: Option<u64>,
 }
 
-#[derive(serde::Serialize)]
-struct ClientRow {
-    client: ClientId,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
-    locked: bool,
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> anyhow::Result<String> {
+    args.next()
+        .with_context(|| format!("{flag} expects a value"))
 }
 
-fn process_csv<R: io::Read, W: io::Write>(reader: R, writer: W) -> anyhow::Result<()> {
-    let mut clients = ClientDb::default();
-    let mut transactions = TransactionDb::default();
+fn days_value(args: &mut impl Iterator<Item = String>, flag: &str) -> anyhow::Result<Duration> {
+    let days: u64 = flag_value(args, flag)?
+        .parse()
+        .with_context(|| format!("invalid {flag}"))?;
+    let seconds = days
+        .checked_mul(24 * 60 * 60)
+        .context("duration is too long")?;
+    Ok(Duration::from_secs(seconds))
+}
 
-    // read & update client accounts
-    {
-        let mut reader = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .has_headers(true)
-            .from_reader(reader);
-        for (idx, result) in reader.deserialize().enumerate() {
-            let operation: Operation = result.unwrap();
-            if let Err(error) = process_operation(&mut clients, &mut transactions, &operation) {
-                eprintln!("row #{idx}: {error}");
+impl Command {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = std::env::args().skip(1).peekable();
+        match args.peek().map(String::as_str) {
+            Some("replay") => {
+                args.next();
+                Self::parse_replay(args)
+            }
+            Some("diff") => {
+                args.next();
+                let [before_path, after_path] = <[String; 2]>::try_from(args.collect::<Vec<_>>())
+                    .ok()
+                    .context("diff expects two event log filenames")?;
+                Ok(Self::Diff(DiffArgs {
+                    before_path,
+                    after_path,
+                }))
+            }
+            Some("reconcile") => {
+                args.next();
+                let mut process_args = Self::parse_process(args)?;
+                let expected_path = process_args
+                    .positional
+                    .pop()
+                    .filter(|_| !process_args.positional.is_empty())
+                    .context("reconcile expects input and expected balances filenames")?;
+                process_args.args.input_paths = process_args.positional;
+                process_args.args.expected_path = Some(expected_path);
+                Ok(Self::Reconcile(Box::new(process_args.args)))
+            }
+            _ => {
+                let mut process_args = Self::parse_process(args)?;
+                if process_args.positional.is_empty() {
+                    bail!("first arg should be input filename");
+                }
+                process_args.args.input_paths = process_args.positional;
+                Ok(Self::Process(Box::new(process_args.args)))
             }
         }
     }
 
-    let mut writer = csv::WriterBuilder::new()
-        .has_headers(true)
-        .from_writer(writer);
-    for (client, state) in clients.all() {
-        const DECIMAL_PLACES: u32 = 4;
+    fn parse_process(mut args: impl Iterator<Item = String>) -> anyhow::Result<ParsedProcessArgs> {
+        let mut positional = Vec::new();
+        let mut stats = false;
+        let mut outcomes_path = None;
+        let mut events_path = None;
+        let mut as_of_row = None;
+        let mut as_of_time = None;
+        let mut dispute_policy = DisputePolicy::default();
+        let mut overdraft_policy = OverdraftPolicy::default();
+        let mut limits_path = None;
+        let mut rules_path = None;
+        let mut fees_path = None;
+        let mut chargeback_fee = None;
+        let mut admin = false;
+        let mut strict_accounts = false;
+        let mut accounts_path = None;
+        let mut tx_scope = TxScope::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--stats" => stats = true,
+                "--admin" => admin = true,
+                "--strict-accounts" => strict_accounts = true,
+                "--accounts" => accounts_path = Some(flag_value(&mut args, &arg)?),
+                "--tx-scope" => {
+                    tx_scope = match flag_value(&mut args, &arg)?.as_str() {
+                        "global" => TxScope::Global,
+                        "client" => TxScope::Client,
+                        "source" => TxScope::Source,
+                        other => bail!("invalid --tx-scope '{other}'"),
+                    };
+                }
+                "--outcomes" => outcomes_path = Some(flag_value(&mut args, &arg)?),
+                "--events" => events_path = Some(flag_value(&mut args, &arg)?),
+                "--as-of-row" => {
+                    let row = flag_value(&mut args, &arg)?;
+                    as_of_row = Some(row.parse().context("invalid --as-of-row")?);
+                }
+                "--as-of-time" => {
+                    let time = flag_value(&mut args, &arg)?;
+                    as_of_time = Some(time.parse().context("invalid --as-of-time")?);
+                }
+                "--dispute-window" => {
+                    dispute_policy.window = Some(days_value(&mut args, &arg)?);
+                }
+                "--max-dispute-duration" => {
+                    dispute_policy.max_duration = Some(days_value(&mut args, &arg)?);
+                }
+                "--on-dispute-expiry" => {
+                    dispute_policy.on_expiry = match flag_value(&mut args, &arg)?.as_str() {
+                        "resolve" => Some(DisputeExpiry::Resolve),
+                        "chargeback" => Some(DisputeExpiry::Chargeback),
+                        other => bail!("invalid --on-dispute-expiry '{other}'"),
+                    };
+                }
+                "--overdraft" => {
+                    overdraft_policy = match flag_value(&mut args, &arg)?.as_str() {
+                        "reject" => OverdraftPolicy::Reject,
+                        "allow" => OverdraftPolicy::Allow,
+                        limit => OverdraftPolicy::Limit {
+                            default: limit.parse().context("invalid --overdraft")?,
+                            clients: Default::default(),
+                        },
+                    };
+                }
+                "--limits" => limits_path = Some(flag_value(&mut args, &arg)?),
+                "--rules" => rules_path = Some(flag_value(&mut args, &arg)?),
+                "--fees" => fees_path = Some(flag_value(&mut args, &arg)?),
+                "--chargeback-fee" => {
+                    let fee = flag_value(&mut args, &arg)?;
+                    chargeback_fee = Some(fee.parse().context("invalid --chargeback-fee")?);
+                }
+                "--unlock-on-reversal" => dispute_policy.unlock_on_reversal = true,
+                "--overdraft-limit" => {
+                    let value = flag_value(&mut args, &arg)?;
+                    let (client, limit) = value
+                        .split_once('=')
+                        .context("--overdraft-limit expects <client>=<limit>")?;
+                    let client = ClientId(client.parse().context("invalid client")?);
+                    let limit: Decimal = limit.parse().context("invalid overdraft limit")?;
+                    let OverdraftPolicy::Limit { clients, .. } = &mut overdraft_policy else {
+                        bail!("--overdraft-limit requires --overdraft <default limit> first");
+                    };
+                    clients.insert(client, limit);
+                }
+                flag if flag.starts_with("--") => bail!("unknown flag '{flag}'"),
+                _ => positional.push(arg),
+            }
+        }
+        if dispute_policy.on_expiry.is_some() && dispute_policy.max_duration.is_none() {
+            bail!("--on-dispute-expiry requires --max-dispute-duration");
+        }
+        let args = ProcessArgs {
+            input_paths: Vec::new(),
+            expected_path: None,
+            stats,
+            outcomes_path,
+            events_path,
+            as_of_row,
+            as_of_time,
+            dispute_policy,
+            overdraft_policy,
+            limits_path,
+            rules_path,
+            fees_path,
+            chargeback_fee,
+            admin,
+            strict_accounts,
+            accounts_path,
+            tx_scope,
+        };
+        Ok(ParsedProcessArgs { args, positional })
+    }
 
-        writer.serialize(ClientRow {
-            client,
-            available: state.available().round_dp(DECIMAL_PLACES),
-            held: state.held().round_dp(DECIMAL_PLACES),
-            total: state.total().round_dp(DECIMAL_PLACES),
-            locked: state.is_locked(),
-        })?;
+    fn parse_replay(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut log_path = None;
+        let mut until_seq = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--until-seq" => {
+                    let seq = flag_value(&mut args, &arg)?;
+                    until_seq = Some(seq.parse().context("invalid --until-seq")?);
+                }
+                flag if flag.starts_with("--") => bail!("unknown flag '{flag}'"),
+                _ if log_path.is_none() => log_path = Some(arg),
+                _ => bail!("unexpected argument '{arg}'"),
+            }
+        }
+        let Some(log_path) = log_path else {
+            bail!("replay expects event log filename");
+        };
+        Ok(Self::Replay(ReplayArgs {
+            log_path,
+            until_seq,
+        }))
     }
-    writer.flush()?;
-    Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let Some(input_path) = std::env::args().nth(1) else {
-        bail!("first arg should be input filename");
-    };
-
-    process_csv(
-        File::open(&input_path).with_context(|| format!("cannot open file '{input_path}'"))?,
-        io::stdout(),
-    )
+fn open_file(path: &str) -> anyhow::Result<File> {
+    File::open(path).with_context(|| format!("cannot open file '{path}'"))
 }
 
-#[cfg(test)]
-mod tests {
-    use indoc::indoc;
-    use similar_asserts::assert_eq;
-
-    use super::*;
-
-    #[test]
-    fn test_chargeback() {
-        let mut clients = ClientDb::default();
-        let mut transactions = TransactionDb::default();
-
-        // deposit 5.0
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Deposit,
-                client: ClientId(123),
-                tx: TransactionId(999),
-                amount: Some(5.into()),
-            },
-        )
-        .unwrap();
-
-        // deposit 2.0
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Deposit,
-                client: ClientId(123),
-                tx: TransactionId(256),
-                amount: Some(2.into()),
-            },
-        )
-        .unwrap();
-
-        // dispute
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Dispute,
-                client: ClientId(123),
-                tx: TransactionId(256),
-                amount: None,
-            },
-        )
-        .unwrap();
-
-        let client = clients.get_mut(ClientId(123));
-        assert_eq!(client.available(), Decimal::from(5));
-        assert_eq!(client.held(), Decimal::from(2));
-        assert_eq!(client.total(), Decimal::from(7));
-        assert_eq!(client.is_locked(), false);
+fn create_file(path: &str) -> anyhow::Result<BufWriter<File>> {
+    let file = File::create(path).with_context(|| format!("cannot create file '{path}'"))?;
+    Ok(BufWriter::new(file))
+}
 
-        // chargeback
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Chargeback,
-                client: ClientId(123),
-                tx: TransactionId(256),
-                amount: None,
-            },
-        )
-        .unwrap();
+/// Peak resident set size in kB (Linux only).
+fn peak_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
 
-        let client = clients.get_mut(ClientId(123));
-        assert_eq!(client.available(), Decimal::from(5));
-        assert_eq!(client.held(), Decimal::from(0));
-        assert_eq!(client.total(), Decimal::from(5));
-        assert_eq!(client.is_locked(), true);
+/// Processes input, then writes balances (or differences from the expected ones when
+/// reconciling). Fails (exit code) if there are differences.
+fn process(args: ProcessArgs) -> anyhow::Result<ExitCode> {
+    let mut outcomes = args.outcomes_path.as_deref().map(create_file).transpose()?;
+    let mut events = args.events_path.as_deref().map(create_file).transpose()?;
+    let limits = match &args.limits_path {
+        Some(path) => LimitsConfig::from_reader(open_file(path)?)?,
+        None => LimitsConfig::default(),
+    };
+    let rules = match &args.rules_path {
+        Some(path) => read_rules(open_file(path)?)?,
+        None => Vec::new(),
+    };
+    let accounts = match &args.accounts_path {
+        Some(path) => read_accounts(open_file(path)?)
+            .collect::<Result<_, _>>()
+            .context("invalid accounts file")?,
+        None => Vec::new(),
+    };
+    let expected = match &args.expected_path {
+        Some(path) => Some(
+            read_accounts(open_file(path)?)
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid expected balances file")?,
+        ),
+        None => None,
+    };
+    let mut fees = match &args.fees_path {
+        Some(path) => FeeSchedule::from_reader(open_file(path)?)?,
+        None => FeeSchedule::default(),
+    };
+    if let Some(flat) = args.chargeback_fee {
+        let fee = Fee {
+            flat: Some(flat),
+            ..Fee::default()
+        };
+        fees.default.insert(OperationType::Chargeback, fee);
     }
 
-    #[test]
-    fn test_resolve() {
-        let mut clients = ClientDb::default();
-        let mut transactions = TransactionDb::default();
-
-        // deposit 5.0
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Deposit,
-                client: ClientId(123),
-                tx: TransactionId(999),
-                amount: Some(5.into()),
-            },
-        )
-        .unwrap();
-
-        // deposit 2.0
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Deposit,
-                client: ClientId(123),
-                tx: TransactionId(256),
-                amount: Some(2.into()),
-            },
-        )
-        .unwrap();
-
-        // dispute
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Dispute,
-                client: ClientId(123),
-                tx: TransactionId(256),
-                amount: None,
-            },
-        )
-        .unwrap();
-
-        let client = clients.get_mut(ClientId(123));
-        assert_eq!(client.available(), Decimal::from(5));
-        assert_eq!(client.held(), Decimal::from(2));
-        assert_eq!(client.total(), Decimal::from(7));
-        assert_eq!(client.is_locked(), false);
-
-        // resolve
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Resolve,
-                client: ClientId(123),
-                tx: TransactionId(256),
-                amount: None,
-            },
-        )
-        .unwrap();
-
-        let client = clients.get_mut(ClientId(123));
-        assert_eq!(client.available(), Decimal::from(7));
-        assert_eq!(client.held(), Decimal::from(0));
-        assert_eq!(client.total(), Decimal::from(7));
-        assert_eq!(client.is_locked(), false);
+    let started_at = Instant::now();
+    let inputs = args
+        .input_paths
+        .iter()
+        .map(|path| Ok((Some(path.clone()), open_file(path)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (engine, stats) = process_inputs_into_engine(
+        inputs,
+        Options {
+            outcomes: outcomes.as_mut().map(|file| file as &mut dyn io::Write),
+            events: events.as_mut().map(|file| file as &mut dyn io::Write),
+            until_row: args.as_of_row,
+            until_time: args.as_of_time,
+            history: None,
+            dispute_policy: args.dispute_policy,
+            overdraft_policy: args.overdraft_policy,
+            limits,
+            rules: rules
+                .into_iter()
+                .map(|rule| Box::new(rule) as Box<dyn Rule>)
+                .collect(),
+            fees,
+            admin: args.admin,
+            strict_accounts: args.strict_accounts,
+            accounts,
+            tx_scope: args.tx_scope,
+        },
+    )?;
+
+    let mut exit_code = ExitCode::SUCCESS;
+    match expected {
+        Some(expected) => {
+            let differences = reconcile(engine.clients(), &expected)?;
+            write_differences(&differences, io::stdout())?;
+            if !differences.is_empty() {
+                eprintln!("balances differ: {} differences", differences.len());
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+        None => write_balances(engine.clients(), io::stdout())?,
     }
 
-    #[test]
-    fn test_example() {
-        const INPUT: &str = indoc! {"
-            type, client, tx, amount
-            deposit, 1, 1, 1.0
-            deposit, 2, 2, 2.0
-            deposit, 1, 3, 2.0
-            withdrawal, 1, 4, 1.5
-            withdrawal, 2, 5, 3.0
-        "};
-
-        const OUTPUT: &str = indoc! {"
-            client,available,held,total,locked
-            1,1.5,0,1.5,false
-            2,2,0,2,false
-        "};
-
-        let mut output = Vec::new();
-        process_csv(INPUT.as_bytes(), &mut output).unwrap();
-
-        let output = String::from_utf8(output).unwrap();
-        assert_eq!(output, OUTPUT);
+    if args.stats {
+        print_stats(&stats, started_at);
     }
+    Ok(exit_code)
+}
 
-    #[test]
-    fn test_precision() {
-        const INPUT: &str = indoc! {"
-            type, client, tx, amount
-            deposit, 1, 1, 1000.2303
-            deposit, 1, 2, 2001.1533
-        "};
-
-        const OUTPUT: &str = indoc! {"
-            client,available,held,total,locked
-            1,3001.3836,0,3001.3836,false
-        "};
-
-        let mut output = Vec::new();
-        process_csv(INPUT.as_bytes(), &mut output).unwrap();
-
-        let output = String::from_utf8(output).unwrap();
-        assert_eq!(output, OUTPUT);
+fn print_stats(stats: &Stats, started_at: Instant) {
+    let elapsed = started_at.elapsed();
+    #[allow(clippy::cast_precision_loss)]
+    let rows_per_sec = stats.rows as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    eprintln!(
+        "rows: {}, rejected: {}, duplicates: {}, flagged: {}, held: {}, expired disputes: {}, elapsed: {:.3}s, throughput: {rows_per_sec:.0} rows/sec",
+        stats.rows,
+        stats.rejected,
+        stats.duplicates,
+        stats.flagged,
+        stats.held,
+        stats.expired_disputes,
+        elapsed.as_secs_f64(),
+    );
+    #[allow(clippy::cast_precision_loss)]
+    let bytes_per_tx = stats.transaction_bytes as f64 / stats.transactions.max(1) as f64;
+    eprintln!(
+        "transactions: {} ({} disputable), transaction storage: {} bytes ({bytes_per_tx:.1} bytes/tx)",
+        stats.transactions, stats.disputable_transactions, stats.transaction_bytes,
+    );
+    if !stats.fee_revenue.is_zero() {
+        eprintln!("fee revenue: {}", stats.fee_revenue);
     }
-
-    #[test]
-    fn test_output_rounding() {
-        const INPUT: &str = indoc! {"
-            type, client, tx, amount
-            deposit, 1, 1, 9.1333333
-        "};
-
-        const OUTPUT: &str = indoc! {"
-            client,available,held,total,locked
-            1,9.1333,0,9.1333,false
-        "};
-
-        let mut output = Vec::new();
-        process_csv(INPUT.as_bytes(), &mut output).unwrap();
-
-        let output = String::from_utf8(output).unwrap();
-        assert_eq!(output, OUTPUT);
+    match peak_memory_kb() {
+        Some(kb) => eprintln!("peak memory: {kb} kB"),
+        None => eprintln!("peak memory: unavailable"),
     }
+}
 
-    #[test]
-    fn test_negative_deposit() {
-        let mut clients = ClientDb::default();
-        let mut transactions = TransactionDb::default();
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Deposit,
-                client: ClientId(123),
-                tx: TransactionId(999),
-                amount: Some((-1_i32).into()),
-            },
-        )
-        .unwrap_err();
-    }
+fn replay(args: ReplayArgs) -> anyhow::Result<ExitCode> {
+    let engine = Engine::replay(open_file(&args.log_path)?, args.until_seq)?;
+    eprintln!("replayed events up to #{}", engine.last_seq());
+    write_balances(engine.clients(), io::stdout())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn diff(args: DiffArgs) -> anyhow::Result<ExitCode> {
+    let before = Engine::replay(open_file(&args.before_path)?, None)?;
+    let after = Engine::replay(open_file(&args.after_path)?, None)?;
+    let diff = diff_states(&before, &after);
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &diff)?;
+    writeln!(stdout)?;
+    Ok(ExitCode::SUCCESS)
+}
 
-    #[test]
-    fn test_invalid_dispute() {
-        let mut clients = ClientDb::default();
-        let mut transactions = TransactionDb::default();
-        process_operation(
-            &mut clients,
-            &mut transactions,
-            &Operation {
-                op_type: OperationType::Dispute,
-                client: ClientId(123),
-                tx: TransactionId(999),
-                amount: None,
-            },
-        )
-        .unwrap_err();
+fn main() -> anyhow::Result<ExitCode> {
+    match Command::parse()? {
+        Command::Process(args) | Command::Reconcile(args) => process(*args),
+        Command::Replay(args) => replay(args),
+        Command::Diff(args) => diff(args),
     }
 }
@@ -0,0 +1,106 @@
+use std::io;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    transaction::TransactionStatus, ClientId, Engine, Operation, OperationType, Outcome,
+    TransactionId, DECIMAL_PLACES,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutcomeKind {
+    Accepted,
+    Rejected,
+    Duplicate,
+    /// Dispute expired and was resolved/charged back automatically (synthetic operation).
+    Expired,
+    /// Withdrawal was put on hold for review.
+    Held,
+}
+
+/// Outcome of a single input operation, written as one JSON line.
+#[derive(Debug, serde::Serialize)]
+pub struct OutcomeEvent<'a> {
+    /// Index of the input row (0-based, not counting headers).
+    pub row: u64,
+    #[serde(rename = "type")]
+    pub op_type: OperationType,
+    pub client: ClientId,
+    /// Destination client of a transfer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<ClientId>,
+    pub tx: TransactionId,
+    pub outcome: OutcomeKind,
+    /// Rejection reason.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+    /// Names of rules which flagged the operation.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub flags: Vec<&'a str>,
+    /// Status of the transaction after the operation (`None` if it doesn't exist).
+    pub status: Option<TransactionStatus>,
+    /// Balances of the client after the operation.
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// Writes operation outcomes as JSON Lines.
+pub(crate) struct OutcomeWriter<W> {
+    writer: W,
+}
+
+impl<W: io::Write> OutcomeWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        row: u64,
+        operation: &Operation,
+        result: &anyhow::Result<Outcome>,
+        engine: &Engine,
+    ) -> anyhow::Result<()> {
+        let error = result.as_ref().err().map(ToString::to_string);
+        let (available, held, total, locked) = match engine.clients().get(operation.client) {
+            Some(state) => (
+                state.available(),
+                state.held(),
+                state.total(),
+                state.is_locked(),
+            ),
+            None => Default::default(),
+        };
+        let event = OutcomeEvent {
+            row,
+            op_type: operation.op_type,
+            client: operation.client,
+            to: operation.to,
+            tx: operation.tx,
+            outcome: match result {
+                Ok(Outcome::Applied) => OutcomeKind::Accepted,
+                Ok(Outcome::Duplicate) => OutcomeKind::Duplicate,
+                Ok(Outcome::Expired) => OutcomeKind::Expired,
+                Ok(Outcome::Held) => OutcomeKind::Held,
+                Err(_) => OutcomeKind::Rejected,
+            },
+            error: error.as_deref(),
+            flags: engine.last_flags().collect(),
+            status: engine.transaction_status(operation),
+            available: available.round_dp(DECIMAL_PLACES),
+            held: held.round_dp(DECIMAL_PLACES),
+            total: total.round_dp(DECIMAL_PLACES),
+            locked,
+        };
+        serde_json::to_writer(&mut self.writer, &event)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
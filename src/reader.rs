@@ -0,0 +1,455 @@
+use std::io;
+
+use rust_decimal::Decimal;
+
+use crate::{ClientId, Operation, OperationType, TransactionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("record (line {line:?}) isn't valid UTF-8")]
+    Utf8 { line: Option<u64> },
+    /// The record is valid apart from its timestamp (0 or after 2106), `operation` has none.
+    #[error("{error}")]
+    InvalidTimestamp {
+        operation: Box<Operation>,
+        error: &'static str,
+    },
+}
+
+/// Column indices of operation fields (resolved from headers).
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    op_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    timestamp: Option<usize>,
+    to: Option<usize>,
+    reason: Option<usize>,
+    source: Option<usize>,
+}
+
+impl Columns {
+    /// Returns `None` when headers aren't in a shape fast path can handle (missing or
+    /// duplicated fields). Every record is then deserialized with serde, which reports the
+    /// proper error.
+    fn from_headers(headers: &csv::StringRecord) -> Option<Self> {
+        let (mut op_type, mut client, mut tx) = (None, None, None);
+        let (mut amount, mut timestamp, mut to, mut reason) = (None, None, None, None);
+        let mut source = None;
+        for (idx, header) in headers.iter().enumerate() {
+            let column = match header {
+                "type" => &mut op_type,
+                "client" => &mut client,
+                "tx" => &mut tx,
+                "amount" => &mut amount,
+                "timestamp" => &mut timestamp,
+                "to" => &mut to,
+                "reason" => &mut reason,
+                "source" => &mut source,
+                _ => continue,
+            };
+            if column.replace(idx).is_some() {
+                return None;
+            }
+        }
+        Some(Self {
+            op_type: op_type?,
+            client: client?,
+            tx: tx?,
+            amount,
+            timestamp,
+            to,
+            reason,
+            source,
+        })
+    }
+}
+
+/// Reads operations from CSV input, accepting exactly the same inputs as
+/// [`read_operations`](crate::read_operations) but considerably faster.
+///
+/// Records are read into a single reused [`csv::ByteRecord`] (no per-record allocation) and
+/// common field formats are parsed by hand. Anything unusual (non-ASCII data, hex ids,
+/// signed or exponent amounts, parse errors, ...) falls back to serde deserialization of
+/// that record, so results and errors are the same as with serde.
+pub struct OperationReader<R> {
+    reader: csv::Reader<R>,
+    record: csv::ByteRecord,
+    /// Trimmed headers, as seen by serde deserialization.
+    headers: Option<csv::StringRecord>,
+    columns: Option<Columns>,
+}
+
+impl<R: io::Read> OperationReader<R> {
+    pub fn new(reader: R) -> Self {
+        // note: Trimming is done while parsing fields, `csv::Trim::All` would allocate a new
+        // record for every row.
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::None)
+            .has_headers(true)
+            .from_reader(reader);
+        let headers = reader.headers().ok().map(|headers| {
+            let mut headers = headers.clone();
+            headers.trim();
+            headers
+        });
+        let columns = headers.as_ref().and_then(Columns::from_headers);
+        Self {
+            reader,
+            record: csv::ByteRecord::new(),
+            headers,
+            columns,
+        }
+    }
+
+    fn parse_fast(&self, columns: Columns) -> Option<Operation> {
+        if !self.record.as_slice().is_ascii() {
+            return None;
+        }
+        let field = |idx: usize| self.record.get(idx).map(<[u8]>::trim_ascii);
+        let amount = match columns.amount {
+            Some(idx) => match field(idx)? {
+                b"" => None,
+                amount => Some(parse_amount(amount)?),
+            },
+            None => None,
+        };
+        let timestamp = match columns.timestamp {
+            Some(idx) => match field(idx)? {
+                b"" => None,
+                // note: 0 is rejected (see `Self::invalid_timestamp`).
+                timestamp => Some(parse_int(timestamp).filter(|time: &u32| *time != 0)?),
+            },
+            None => None,
+        };
+        let to = match columns.to {
+            Some(idx) => match field(idx)? {
+                b"" => None,
+                to => Some(ClientId(parse_int(to)?)),
+            },
+            None => None,
+        };
+        let text = |column: Option<usize>| match column {
+            Some(idx) => match field(idx)? {
+                b"" => Some(None),
+                text => Some(Some(String::from_utf8(text.to_vec()).ok()?)),
+            },
+            None => Some(None),
+        };
+        Some(Operation {
+            op_type: parse_op_type(field(columns.op_type)?)?,
+            client: ClientId(parse_int(field(columns.client)?)?),
+            tx: TransactionId(parse_int(field(columns.tx)?)?),
+            amount,
+            timestamp,
+            to,
+            reason: text(columns.reason)?,
+            source: text(columns.source)?,
+        })
+    }
+
+    fn parse_with_serde(&self) -> Result<Operation, ReadError> {
+        let mut record =
+            csv::StringRecord::from_byte_record(self.record.clone()).map_err(|_| {
+                ReadError::Utf8 {
+                    line: self.record.position().map(csv::Position::line),
+                }
+            })?;
+        record.trim();
+        Ok(record.deserialize(self.headers.as_ref())?)
+    }
+
+    fn parse(&self) -> Result<Operation, ReadError> {
+        match self.columns.and_then(|columns| self.parse_fast(columns)) {
+            Some(operation) => Ok(operation),
+            None => self.parse_with_serde(),
+        }
+    }
+
+    /// [`ReadError::InvalidTimestamp`] if the record's timestamp is out of range and it's
+    /// valid otherwise.
+    fn invalid_timestamp(&mut self) -> Option<ReadError> {
+        let idx = self.columns?.timestamp?;
+        let error = match parse_int::<u64>(self.record.get(idx)?.trim_ascii())? {
+            0 => "timestamp should be > 0",
+            timestamp if u32::try_from(timestamp).is_err() => {
+                "timestamp is out of range (after 2106)"
+            }
+            _ => return None,
+        };
+        let mut record = csv::ByteRecord::new();
+        for (field_idx, field) in self.record.iter().enumerate() {
+            record.push_field(if field_idx == idx { b"" } else { field });
+        }
+        record.set_position(self.record.position().cloned());
+        self.record = record;
+        let operation = self.parse().ok()?;
+        Some(ReadError::InvalidTimestamp {
+            operation: Box::new(operation),
+            error,
+        })
+    }
+}
+
+impl<R: io::Read> Iterator for OperationReader<R> {
+    type Item = Result<Operation, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(error) => return Some(Err(error.into())),
+        }
+        let result = self.parse();
+        if result.is_err() {
+            if let Some(error) = self.invalid_timestamp() {
+                return Some(Err(error));
+            }
+        }
+        Some(result)
+    }
+}
+
+fn parse_op_type(field: &[u8]) -> Option<OperationType> {
+    Some(match field {
+        b"deposit" => OperationType::Deposit,
+        b"withdrawal" => OperationType::Withdrawal,
+        b"dispute" => OperationType::Dispute,
+        b"resolve" => OperationType::Resolve,
+        b"chargeback" => OperationType::Chargeback,
+        b"approve" => OperationType::Approve,
+        b"decline" => OperationType::Decline,
+        b"authorize" => OperationType::Authorize,
+        b"capture" => OperationType::Capture,
+        b"void" => OperationType::Void,
+        b"transfer" => OperationType::Transfer,
+        b"reversal" => OperationType::Reversal,
+        b"lock" => OperationType::Lock,
+        b"unlock" => OperationType::Unlock,
+        b"close" => OperationType::Close,
+        b"open" => OperationType::Open,
+        _ => return None,
+    })
+}
+
+/// Parses plain decimal digits (no sign, no hex prefix).
+fn parse_int<T: TryFrom<u64>>(field: &[u8]) -> Option<T> {
+    if field.is_empty() || field.len() > 19 {
+        return None;
+    }
+    let mut value = 0_u64;
+    for &byte in field {
+        let digit = char::from(byte).to_digit(10)?;
+        value = value.checked_mul(10)?.checked_add(u64::from(digit))?;
+    }
+    T::try_from(value).ok()
+}
+
+/// Parses `digits` or `digits.digits` amounts.
+///
+/// Serde deserialization of `Decimal` goes through csv type inference: integers are
+/// converted exactly, while fractional numbers are parsed as `f64` and converted back
+/// from their shortest string representation (`1.50` becomes `1.5`). Both agree with the
+/// normalized decimal as long as there are at most 15 digits, which is what `f64`
+/// round-trips losslessly. Anything longer is left to serde.
+fn parse_amount(field: &[u8]) -> Option<Decimal> {
+    const MAX_FLOAT_DIGITS: usize = 15;
+
+    let Some(dot) = field.iter().position(|&byte| byte == b'.') else {
+        return parse_int::<u64>(field).map(Decimal::from);
+    };
+    let (integer, fraction) = (&field[..dot], &field[dot.checked_add(1)?..]);
+    if integer.is_empty()
+        || fraction.is_empty()
+        || integer.len().checked_add(fraction.len())? > MAX_FLOAT_DIGITS
+    {
+        return None;
+    }
+    let mantissa = parse_int::<u64>(integer)?
+        .checked_mul(10_u64.checked_pow(u32::try_from(fraction.len()).ok()?)?)?
+        .checked_add(parse_int::<u64>(fraction)?)?;
+    let scale = u32::try_from(fraction.len()).ok()?;
+    Some(Decimal::from_i128_with_scale(i128::from(mantissa), scale).normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    /// Reads input with both readers and checks they produce the same operations (including
+    /// decimal scale) and fail on the same rows.
+    fn assert_same_as_serde(input: &str) {
+        let expected: Vec<_> = crate::read_operations(input.as_bytes())
+            .map(|result| result.map(|op| format!("{op:?}")).map_err(|_| ()))
+            .collect();
+        let actual: Vec<_> = OperationReader::new(input.as_bytes())
+            .map(|result| result.map(|op| format!("{op:?}")).map_err(|_| ()))
+            .collect();
+        assert_eq!(actual, expected, "input: {input}");
+    }
+
+    #[test]
+    fn test_same_as_serde() {
+        assert_same_as_serde(indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 1.0
+            deposit, 2, 2, 2.0
+            withdrawal, 1, 4, 1.5
+            dispute, 1, 1,
+            resolve, 1, 1,
+            chargeback, 1, 1,
+            reversal, 1, 1,
+            approve, 1, 4,
+            decline, 1, 4,
+            authorize, 1, 30, 2.5
+            capture, 1, 30, 1.0
+            capture, 1, 30,
+            void, 1, 30,
+            deposit,1,5,9.1333333
+            deposit,\t1 ,\t6 ,   1000.2303
+            deposit, 1, 7, 0.0001
+            deposit, 1, 8, 00012.3400
+            deposit, 1, 9, 123456789012345678
+            deposit, 1, 10, 12345678901234567890123
+            deposit, 1, 11, 1234567890.123456789
+            deposit, 1, 12, 0.1234567890123456789
+            deposit, 1, 13, 1e3
+            deposit, 1, 14, .5
+            deposit, 1, 15, 5.
+            deposit, 1, 16, -1.5
+            deposit, 1, 17, +1.5
+            deposit, 1, 18, -0
+            deposit, 1, 19, 1.2.3
+            deposit, 0x10, 0x20, 1
+            deposit, +1, 20, 1
+            deposit, 65535, 4294967295, 1
+            deposit, 65536, 1, 1
+            deposit, 1, 4294967296, 1
+            deposit, 18446744073709551615, 18446744073709551615, 1
+            deposit, 18446744073709551616, 1, 1
+            deposit, 1, 18446744073709551616, 1
+            deposit, -1, 1, 1
+            deposit, , 1, 1
+            deposit, 1, , 1
+            Deposit, 1, 21, 1
+            refund, 1, 22, 1
+            dispute, 1, 23, 1
+            deposit, 1, 24, abc
+            deposit, 1, 25, 1,5
+            deposit\u{a0}, 1, 26, 2
+            deposit, 1, 27, \u{2003}3.5
+        "});
+    }
+
+    #[test]
+    fn test_same_as_serde_headers() {
+        // different order, extra and missing columns
+        assert_same_as_serde("tx,amount,extra,type,client\n1,1.5,x,deposit,2\n");
+        assert_same_as_serde("type,client,tx\ndeposit,1,1\ndispute,1,1\n");
+        assert_same_as_serde("type,client,amount\ndeposit,1,1\n");
+        assert_same_as_serde("type,client,tx,tx,amount\ndeposit,1,1,1,1\n");
+        assert_same_as_serde(" type , client\u{a0}, tx, amount\ndeposit,1,1,1\n");
+        // optional timestamp column
+        assert_same_as_serde(indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 1.0, 1700000000
+            deposit, 1, 2, 1.0,
+            dispute, 1, 1, , 4294967295
+            dispute, 1, 1, , 4294967296
+            dispute, 1, 1, , 0
+            dispute, 1, 1, , 00
+            dispute, 1, 1, , -1
+            dispute, 1, 1, , 1.5
+        "});
+        // optional reason column
+        assert_same_as_serde(indoc! {"
+            type, client, tx, amount, reason
+            lock, 1, 0, , suspected fraud
+            unlock, 1, 0, ,
+            close, 1, 0, , \"closed, on request\"
+            open, 2, 0, ,
+            lock, 1, 0, 1,  spaces  around
+        "});
+        // optional source column
+        assert_same_as_serde(indoc! {"
+            type, client, tx, amount, source
+            deposit, 1, 1, 1.0, partner-a
+            deposit, 1, 2, 1.0,
+            dispute, 1, 1, , \"partner, b\"
+        "});
+        // optional destination column
+        assert_same_as_serde(indoc! {"
+            type, client, tx, amount, to
+            transfer, 1, 1, 1.0, 2
+            deposit, 1, 2, 1.0,
+            transfer, 1, 3, 1.0, 65536
+            transfer, 1, 4, 1.0, -1
+        "});
+        // unequal lengths
+        assert_same_as_serde("type,client,tx,amount\ndeposit,1,1\ndeposit,1,2,1\n");
+    }
+
+    #[test]
+    fn test_same_as_serde_amounts() {
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        for _ in 0..10_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let digits = seed.to_string();
+            let len = usize::try_from(seed % 19).unwrap().saturating_add(1);
+            let dot = usize::try_from((seed >> 8) % 20).unwrap();
+            let digits = &digits[..len.min(digits.len())];
+            let amount = if dot < digits.len() && dot > 0 {
+                format!("{}.{}", &digits[..dot], &digits[dot..])
+            } else {
+                digits.to_owned()
+            };
+            input.push_str(&format!("deposit,1,1,{amount}\n"));
+        }
+        assert_same_as_serde(&input);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        // invalid data in an ignored column is still an error, as with serde
+        let input = b"type,client,tx,amount,note\ndeposit,1,1,1,\ndeposit,1,2,1,\xff\n";
+        let results: Vec<_> = OperationReader::new(&input[..]).collect();
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(ReadError::Utf8 { line: Some(3) })));
+    }
+
+    #[test]
+    fn test_invalid_timestamp() {
+        // the rest of the row is still read, a row with other invalid fields is an error
+        let input = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 1.5, 0
+            deposit, 1, 2, 1.5, 4294967296
+            deposit, 1, x, 1.5, 0
+        "};
+        let results: Vec<_> = OperationReader::new(input.as_bytes()).collect();
+        let Err(ReadError::InvalidTimestamp { operation, error }) = &results[0] else {
+            panic!("unexpected result: {:?}", results[0]);
+        };
+        assert_eq!(error, &"timestamp should be > 0");
+        assert_eq!(operation.tx, TransactionId(1));
+        assert_eq!(operation.amount, Some(Decimal::new(15, 1)));
+        assert_eq!(operation.timestamp, None);
+        assert!(matches!(
+            &results[1],
+            Err(ReadError::InvalidTimestamp {
+                error: "timestamp is out of range (after 2106)",
+                ..
+            })
+        ));
+        assert!(matches!(results[2], Err(ReadError::Csv(_))));
+    }
+}
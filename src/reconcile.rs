@@ -0,0 +1,160 @@
+use std::io;
+
+use anyhow::ensure;
+use rust_decimal::Decimal;
+use rustc_hash::FxHashSet;
+
+use crate::{ClientDb, ClientId, OpeningAccount, DECIMAL_PLACES};
+
+/// What differs between expected and actual balances of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DifferenceKind {
+    /// Client is expected, but has no account.
+    Missing,
+    /// Client has an account, but isn't expected.
+    Extra,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Difference {
+    pub client: ClientId,
+    pub kind: DifferenceKind,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// Compares balances with expected ones (e.g. an accounts file), returns differences of
+/// expected clients (in order) followed by extra clients. Balances are compared as output
+/// (rounded), `total` only if it's expected.
+pub fn reconcile(
+    clients: &ClientDb,
+    expected: &[OpeningAccount],
+) -> anyhow::Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    let mut seen = FxHashSet::default();
+    for account in expected {
+        let client = account.client;
+        ensure!(seen.insert(client), "client {} is expected twice", client.0);
+        let Some(state) = clients.get(client) else {
+            differences.push(Difference {
+                client,
+                kind: DifferenceKind::Missing,
+                expected: None,
+                actual: None,
+            });
+            continue;
+        };
+        let mut compare = |kind, expected: String, actual: String| {
+            if expected != actual {
+                differences.push(Difference {
+                    client,
+                    kind,
+                    expected: Some(expected),
+                    actual: Some(actual),
+                });
+            }
+        };
+        let decimals = |expected: Decimal, actual: Decimal| {
+            (
+                expected.normalize().to_string(),
+                actual.round_dp(DECIMAL_PLACES).normalize().to_string(),
+            )
+        };
+        let (expected, actual) = decimals(account.available, state.available());
+        compare(DifferenceKind::Available, expected, actual);
+        let (expected, actual) = decimals(account.held, state.held());
+        compare(DifferenceKind::Held, expected, actual);
+        if let Some(total) = account.total {
+            let (expected, actual) = decimals(total, state.total());
+            compare(DifferenceKind::Total, expected, actual);
+        }
+        compare(
+            DifferenceKind::Locked,
+            account.locked.to_string(),
+            state.is_locked().to_string(),
+        );
+    }
+    for (client, _) in clients.all() {
+        if !seen.contains(&client) {
+            differences.push(Difference {
+                client,
+                kind: DifferenceKind::Extra,
+                expected: None,
+                actual: None,
+            });
+        }
+    }
+    Ok(differences)
+}
+
+/// Writes differences as CSV (`client,kind,expected,actual`).
+pub fn write_differences<W: io::Write>(
+    differences: &[Difference],
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_writer(writer);
+    for difference in differences {
+        writer.serialize(difference)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+    use crate::{process_csv_into_engine, read_accounts, Options};
+
+    #[test]
+    fn test_reconcile() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 2, 2.0
+            dispute, 2, 2,
+            deposit, 4, 3, 1.0
+        "};
+
+        const EXPECTED: &str = indoc! {"
+            client,available,held,total,locked
+            1,5.0000,0,5,false
+            2,2,0,2,true
+            3,1,0,1,false
+        "};
+
+        const DIFFERENCES: &str = indoc! {"
+            client,kind,expected,actual
+            2,available,2,0
+            2,held,0,2
+            2,locked,true,false
+            3,missing,,
+            4,extra,,
+        "};
+
+        let (engine, _) = process_csv_into_engine(INPUT.as_bytes(), Options::default()).unwrap();
+        let expected: Vec<_> = read_accounts(EXPECTED.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let differences = reconcile(engine.clients(), &expected).unwrap();
+        let mut output = Vec::new();
+        write_differences(&differences, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), DIFFERENCES);
+
+        assert_eq!(
+            reconcile(engine.clients(), &expected[..1]).unwrap().len(),
+            2
+        );
+        let twice = [expected[0].clone(), expected[0].clone()];
+        assert!(reconcile(engine.clients(), &twice).is_err());
+    }
+}
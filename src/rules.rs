@@ -0,0 +1,337 @@
+use std::{collections::VecDeque, io};
+
+use anyhow::{bail, Context};
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+
+use crate::{AccountState, ClientId, Operation, OperationType};
+
+/// Verdict of a [`Rule`] on an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Apply the operation, but report it as flagged.
+    Flag,
+    /// Put a withdrawal on hold for review (flag other operations).
+    Hold,
+    Reject,
+}
+
+/// Accepted operation of a client, kept for rules which look at recent history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentOperation {
+    pub op_type: OperationType,
+    pub amount: Option<Decimal>,
+    pub timestamp: u32,
+}
+
+/// Custom check evaluated before an operation is applied (after it passed all other checks).
+pub trait Rule {
+    /// Name the rule is reported by.
+    fn name(&self) -> &str;
+
+    /// How far back (seconds) history passed to [`Rule::evaluate`] has to go.
+    fn lookback(&self) -> u32 {
+        0
+    }
+
+    /// `account` is the state before the operation, `history` has client's accepted operations
+    /// within [`Rule::lookback`] (oldest first, only operations with timestamps).
+    fn evaluate(
+        &self,
+        operation: &Operation,
+        account: &AccountState,
+        history: &VecDeque<RecentOperation>,
+    ) -> Verdict;
+}
+
+/// Result of evaluating all rules on an operation which wasn't rejected.
+pub(crate) struct Evaluation {
+    /// Indices of rules which flagged (or held) the operation.
+    pub(crate) flags: Vec<usize>,
+    pub(crate) hold: bool,
+}
+
+/// Registered rules with history of recent operations they need.
+#[derive(Default)]
+pub(crate) struct RuleSet {
+    rules: Vec<Box<dyn Rule>>,
+    lookback: u32,
+    history: FxHashMap<ClientId, VecDeque<RecentOperation>>,
+}
+
+impl RuleSet {
+    pub(crate) fn add(&mut self, rule: Box<dyn Rule>) {
+        self.lookback = self.lookback.max(rule.lookback());
+        self.rules.push(rule);
+    }
+
+    pub(crate) fn name(&self, idx: usize) -> &str {
+        self.rules[idx].name()
+    }
+
+    /// Fails if any rule rejected the operation.
+    pub(crate) fn evaluate(
+        &self,
+        operation: &Operation,
+        account: &AccountState,
+    ) -> anyhow::Result<Evaluation> {
+        let mut evaluation = Evaluation {
+            flags: Vec::new(),
+            hold: false,
+        };
+        if self.rules.is_empty() {
+            return Ok(evaluation);
+        }
+        let empty = VecDeque::new();
+        let history = self.history.get(&operation.client).unwrap_or(&empty);
+        for (idx, rule) in self.rules.iter().enumerate() {
+            match rule.evaluate(operation, account, history) {
+                Verdict::Allow => {}
+                Verdict::Flag => evaluation.flags.push(idx),
+                Verdict::Hold => {
+                    evaluation.flags.push(idx);
+                    evaluation.hold = true;
+                }
+                Verdict::Reject => bail!("rejected by rule '{}'", rule.name()),
+            }
+        }
+        Ok(evaluation)
+    }
+
+    /// Records an accepted operation.
+    pub(crate) fn record(&mut self, operation: &Operation) {
+        let Some(timestamp) = operation.timestamp else {
+            return;
+        };
+        if self.lookback == 0 {
+            return;
+        }
+        let history = self.history.entry(operation.client).or_default();
+        let since = timestamp.saturating_sub(self.lookback);
+        while history
+            .front()
+            .is_some_and(|recent| recent.timestamp < since)
+        {
+            history.pop_front();
+        }
+        history.push_back(RecentOperation {
+            op_type: operation.op_type,
+            amount: operation.amount,
+            timestamp,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Flag,
+    Hold,
+    Reject,
+}
+
+/// Rule from a rules file, matches when all of the set conditions hold.
+///
+/// ```json
+/// [
+///   {
+///     "name": "drain after deposit",
+///     "type": "withdrawal",
+///     "balance_ratio_above": "0.9",
+///     "within_secs_of_deposit": 3600,
+///     "action": "flag"
+///   }
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeclarativeRule {
+    pub name: String,
+    /// Operation type the rule applies to (all if not set).
+    #[serde(rename = "type")]
+    pub op_type: Option<OperationType>,
+    /// Amount is greater than this.
+    pub amount_above: Option<Decimal>,
+    /// Amount is greater than this fraction of available funds.
+    pub balance_ratio_above: Option<Decimal>,
+    /// Client had a deposit accepted at most this many seconds before.
+    pub within_secs_of_deposit: Option<u32>,
+    pub action: RuleAction,
+}
+
+impl DeclarativeRule {
+    fn matches(
+        &self,
+        operation: &Operation,
+        account: &AccountState,
+        history: &VecDeque<RecentOperation>,
+    ) -> Option<bool> {
+        if self
+            .op_type
+            .is_some_and(|op_type| op_type != operation.op_type)
+        {
+            return Some(false);
+        }
+        if let Some(threshold) = self.amount_above {
+            if operation.amount? <= threshold {
+                return Some(false);
+            }
+        }
+        if let Some(ratio) = self.balance_ratio_above {
+            if operation.amount? <= account.available().checked_mul(ratio)? {
+                return Some(false);
+            }
+        }
+        if let Some(window) = self.within_secs_of_deposit {
+            let since = operation.timestamp?.saturating_sub(window);
+            let deposited = history.iter().any(|recent| {
+                recent.op_type == OperationType::Deposit && recent.timestamp >= since
+            });
+            if !deposited {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+}
+
+impl Rule for DeclarativeRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lookback(&self) -> u32 {
+        self.within_secs_of_deposit.unwrap_or(0)
+    }
+
+    fn evaluate(
+        &self,
+        operation: &Operation,
+        account: &AccountState,
+        history: &VecDeque<RecentOperation>,
+    ) -> Verdict {
+        // note: A condition which can't be evaluated (no amount or timestamp) doesn't match.
+        if self.matches(operation, account, history) != Some(true) {
+            return Verdict::Allow;
+        }
+        match self.action {
+            RuleAction::Flag => Verdict::Flag,
+            RuleAction::Hold => Verdict::Hold,
+            RuleAction::Reject => Verdict::Reject,
+        }
+    }
+}
+
+/// Reads rules file (JSON array of [`DeclarativeRule`]).
+pub fn read_rules<R: io::Read>(reader: R) -> anyhow::Result<Vec<DeclarativeRule>> {
+    serde_json::from_reader(io::BufReader::new(reader)).context("invalid rules file")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use indoc::indoc;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+    use crate::{process_csv_with, Options};
+
+    /// Rejects operations of a single client.
+    struct Blocklist(ClientId);
+
+    impl Rule for Blocklist {
+        fn name(&self) -> &str {
+            "blocklist"
+        }
+
+        fn evaluate(
+            &self,
+            operation: &Operation,
+            _account: &AccountState,
+            _history: &VecDeque<RecentOperation>,
+        ) -> Verdict {
+            if operation.client == self.0 {
+                Verdict::Reject
+            } else {
+                Verdict::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        const INPUT: &str = indoc! {"
+            type, client, tx, amount, timestamp
+            deposit, 1, 1, 10.0, 1000
+            withdrawal, 1, 2, 9.5, 2000
+            deposit, 2, 3, 10.0, 1000
+            withdrawal, 2, 4, 9.5, 5000
+            deposit, 3, 5, 10.0, 1000
+            withdrawal, 3, 6, 5.0, 1100
+            deposit, 4, 7, 10.0, 1000
+        "};
+
+        let rules = read_rules(
+            indoc! {r#"
+                [
+                    {
+                        "name": "drain after deposit",
+                        "type": "withdrawal",
+                        "balance_ratio_above": "0.9",
+                        "within_secs_of_deposit": 3600,
+                        "action": "flag"
+                    },
+                    { "name": "large", "amount_above": "9", "action": "flag" }
+                ]
+            "#}
+            .as_bytes(),
+        )
+        .unwrap();
+        let mut rules: Vec<Box<dyn Rule>> = rules
+            .into_iter()
+            .map(|rule| Box::new(rule) as Box<dyn Rule>)
+            .collect();
+        rules.push(Box::new(Blocklist(ClientId(4))));
+
+        let mut outcomes = Vec::new();
+        let stats = process_csv_with(
+            INPUT.as_bytes(),
+            io::sink(),
+            Options {
+                outcomes: Some(&mut outcomes),
+                rules,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.flagged, 5);
+        assert_eq!(stats.rejected, 1);
+
+        let outcomes: Vec<_> = String::from_utf8(outcomes)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let outcome: serde_json::Value = serde_json::from_str(line).unwrap();
+                (outcome["flags"].to_string(), outcome["error"].to_string())
+            })
+            .collect();
+        let flags = |flags: &str| (flags.to_owned(), "null".to_owned());
+        assert_eq!(
+            outcomes,
+            [
+                flags(r#"["large"]"#),
+                flags(r#"["drain after deposit","large"]"#),
+                flags(r#"["large"]"#),
+                flags(r#"["large"]"#),
+                flags(r#"["large"]"#),
+                flags("null"),
+                (
+                    "null".to_owned(),
+                    r#""rejected by rule 'blocklist'""#.to_owned()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::{ensure, Context};
+use indexmap::IndexSet;
+use rustc_hash::{FxBuildHasher, FxHashMap};
+
+use crate::{ClientId, Event, Operation, OperationType, TransactionId};
+
+/// Namespace in which transaction ids are unique.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TxScope {
+    /// One namespace for all transactions.
+    #[default]
+    Global,
+    /// Per client, different clients can use the same ids. A transfer is in the scope of both
+    /// its clients, so its id has to be unused by both.
+    Client,
+    /// Per source (`source` column, or input file), different sources can use the same ids.
+    Source,
+}
+
+/// Transaction id in its scope, as in input. Scope is `client` or `source`, neither if ids are
+/// global.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct ScopedTxId {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub tx: TransactionId,
+}
+
+/// Maps scoped transaction ids to internal ones, which are unique and sequential (so they're
+/// stored densely). Disputes etc. refer to transactions in their own scope.
+#[derive(Default)]
+pub(crate) struct ScopedIds {
+    scope: TxScope,
+    sources: IndexSet<String, FxBuildHasher>,
+    /// Internal ids by scope (client id or source index) and scoped id.
+    ids: FxHashMap<(u64, TransactionId), TransactionId>,
+    /// Scope and scoped id, indexed by internal id.
+    scoped: Vec<(u64, TransactionId)>,
+}
+
+impl ScopedIds {
+    pub(crate) fn new(scope: TxScope) -> Self {
+        Self {
+            scope,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn tx_scope(&self) -> TxScope {
+        self.scope
+    }
+
+    /// Scope of the operation's transaction, `None` if ids are global (or the operation has no
+    /// transaction).
+    fn scope(&self, operation: &Operation) -> anyhow::Result<Option<u64>> {
+        if operation.op_type.is_admin() || operation.op_type == OperationType::Open {
+            return Ok(None);
+        }
+        Ok(match self.scope {
+            TxScope::Global => None,
+            TxScope::Client => Some(operation.client.0),
+            TxScope::Source => {
+                let source = operation
+                    .source
+                    .as_deref()
+                    .context("no source for transaction")?;
+                let idx = self
+                    .sources
+                    .get_index_of(source)
+                    .unwrap_or(self.sources.len());
+                Some(u64::try_from(idx)?)
+            }
+        })
+    }
+
+    /// Internal id of the operation's transaction. A transaction created by the operation gets
+    /// the next unused id, which is only assigned once it's accepted (see [`Self::event`]), as
+    /// do other operations if their transaction doesn't exist.
+    pub(crate) fn resolve(&self, operation: &Operation) -> anyhow::Result<TransactionId> {
+        let Some(scope) = self.scope(operation)? else {
+            return Ok(operation.tx);
+        };
+        // note: A transfer whose id is taken in the destination's scope resolves to that
+        // transaction, so it's rejected as a reuse of the id.
+        let id = self.ids.get(&(scope, operation.tx)).or_else(|| {
+            let to = self.transfer_destination(operation)?;
+            self.ids.get(&(to.0, operation.tx))
+        });
+        match id {
+            Some(id) => Ok(*id),
+            None => Ok(TransactionId(u64::try_from(self.scoped.len())?)),
+        }
+    }
+
+    /// Destination of a transfer, if it's in the destination's scope too.
+    fn transfer_destination(&self, operation: &Operation) -> Option<ClientId> {
+        (self.scope == TxScope::Client && operation.op_type == OperationType::Transfer)
+            .then_some(operation.to)
+            .flatten()
+    }
+
+    /// Event recording the scoped id of the transaction created by an accepted operation (`tx`
+    /// is its internal id), `None` if ids are global or the operation creates no transaction.
+    pub(crate) fn event(&self, operation: &Operation, tx: TransactionId) -> Option<Event> {
+        let creates = matches!(
+            operation.op_type,
+            OperationType::Deposit
+                | OperationType::Withdrawal
+                | OperationType::Authorize
+                | OperationType::Transfer
+        );
+        (creates && self.scope != TxScope::Global).then(|| Event::TransactionScoped {
+            client: operation.client,
+            tx,
+            scoped_tx: operation.tx,
+            to: self.transfer_destination(operation),
+            source: match self.scope {
+                TxScope::Source => operation.source.clone(),
+                TxScope::Global | TxScope::Client => None,
+            },
+        })
+    }
+
+    /// Assigns an internal id to a scoped transaction ([`Event::TransactionScoped`]), in the
+    /// scope of transfer destination `to` as well. When replaying an event log, the scope is set
+    /// by the first one.
+    pub(crate) fn insert(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        scoped_tx: TransactionId,
+        to: Option<ClientId>,
+        source: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let tx_scope = match source {
+            Some(_) => TxScope::Source,
+            None => TxScope::Client,
+        };
+        if self.scope == TxScope::Global && self.scoped.is_empty() {
+            self.scope = tx_scope;
+        }
+        ensure!(
+            self.scope == tx_scope,
+            "transaction isn't in {:?} scope",
+            self.scope
+        );
+        ensure!(
+            tx.0 == u64::try_from(self.scoped.len())?,
+            "unexpected internal transaction id {}",
+            tx.0
+        );
+        let scope = match source {
+            Some(source) => {
+                let idx = self.sources.get_index_of(source);
+                u64::try_from(idx.unwrap_or(self.sources.len()))?
+            }
+            None => client.0,
+        };
+        let key = (scope, scoped_tx);
+        let to_key = to.map(|to| (to.0, scoped_tx));
+        ensure!(
+            !self.ids.contains_key(&key) && to_key.is_none_or(|key| !self.ids.contains_key(&key)),
+            "scoped transaction {} already exists",
+            scoped_tx.0
+        );
+        if let Some(source) = source {
+            self.sources.insert(source.to_owned());
+        }
+        self.ids.insert(key, tx);
+        if let Some(to_key) = to_key {
+            self.ids.insert(to_key, tx);
+        }
+        self.scoped.push(key);
+        Ok(())
+    }
+
+    /// Internal id of the operation's transaction, `None` if it doesn't exist.
+    pub(crate) fn get(&self, operation: &Operation) -> Option<TransactionId> {
+        match self.scope(operation).ok()? {
+            Some(scope) => self.ids.get(&(scope, operation.tx)).copied(),
+            None => Some(operation.tx),
+        }
+    }
+
+    /// Scoped id of a transaction (by its internal id).
+    pub(crate) fn scoped_id(&self, tx: TransactionId) -> ScopedTxId {
+        let scoped = usize::try_from(tx.0)
+            .ok()
+            .and_then(|idx| self.scoped.get(idx));
+        match (self.scope, scoped) {
+            (TxScope::Client, Some(&(client, tx))) => ScopedTxId {
+                client: Some(ClientId(client)),
+                source: None,
+                tx,
+            },
+            (TxScope::Source, Some(&(source, tx))) => ScopedTxId {
+                client: None,
+                source: usize::try_from(source)
+                    .ok()
+                    .and_then(|idx| self.sources.get_index(idx))
+                    .cloned(),
+                tx,
+            },
+            _ => ScopedTxId {
+                client: None,
+                source: None,
+                tx,
+            },
+        }
+    }
+
+    /// Converts an operation with an internal id (e.g. a synthetic one) to a scoped one.
+    pub(crate) fn to_scoped(&self, mut operation: Operation) -> Operation {
+        let scoped = self.scoped_id(operation.tx);
+        operation.tx = scoped.tx;
+        if self.scope == TxScope::Source {
+            operation.source = scoped.source;
+        }
+        operation
+    }
+}
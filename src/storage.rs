@@ -0,0 +1,366 @@
+use std::mem::size_of;
+
+use anyhow::Context;
+use indexmap::IndexSet;
+use rustc_hash::{FxBuildHasher, FxHashMap};
+
+use crate::{
+    client::ClientId,
+    transaction::{TransactionId, TransactionStatus},
+};
+
+/// Transaction state packed into 16 bytes: amount (fixed-point, in units of `10^-scale`) in the
+/// lower 60 bits and status in the upper 4 bits of a 64-bit word, plus the timestamp and client
+/// (as an index into [`ClientIndex`]). All zeroes is reserved for "no transaction".
+///
+/// note: The 64-bit word is split in two `u32`s to keep 4 byte alignment (and 16 byte size).
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub(crate) struct PackedState {
+    bits: [u32; 2],
+    /// Unix time in seconds, 0 if unknown (timestamp 0 is rejected when parsing).
+    time: u32,
+    client: u32,
+}
+
+impl PackedState {
+    const STATUS_SHIFT: u32 = 60;
+    pub(crate) const MAX_UNITS: u64 = (1 << Self::STATUS_SHIFT) - 1;
+
+    pub(crate) fn new(
+        client: u32,
+        units: u64,
+        status: TransactionStatus,
+        timestamp: Option<u32>,
+    ) -> Self {
+        debug_assert!(units <= Self::MAX_UNITS);
+        // note: Status is never 0, so packed state of a transaction is never 0 either.
+        let bits = units | (u64::from(status as u8) << Self::STATUS_SHIFT);
+        #[allow(clippy::cast_possible_truncation)]
+        Self {
+            bits: [bits as u32, (bits >> 32) as u32],
+            time: timestamp.unwrap_or(0),
+            client,
+        }
+    }
+
+    fn bits(self) -> u64 {
+        u64::from(self.bits[0]) | (u64::from(self.bits[1]) << 32)
+    }
+
+    /// Index of the client in [`ClientIndex`].
+    pub(crate) fn client(self) -> u32 {
+        self.client
+    }
+
+    pub(crate) fn units(self) -> u64 {
+        self.bits() & Self::MAX_UNITS
+    }
+
+    pub(crate) fn timestamp(self) -> Option<u32> {
+        (self.time != 0).then_some(self.time)
+    }
+
+    pub(crate) fn status(self) -> TransactionStatus {
+        let status = u8::try_from(self.bits() >> Self::STATUS_SHIFT).expect("4 bit status");
+        TransactionStatus::from_repr(status).expect("valid transaction status")
+    }
+
+    fn is_vacant(self) -> bool {
+        self.bits() == 0
+    }
+}
+
+/// Client (as an index into [`ClientIndex`]) and amount of a withdrawal, packed into 12 bytes. It's
+/// all that's needed to tell a re-submission from a conflicting reuse of its id.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PackedWithdrawal {
+    units: [u32; 2],
+    client: u32,
+}
+
+impl PackedWithdrawal {
+    pub(crate) fn new(client: u32, units: u64) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        Self {
+            units: [units as u32, (units >> 32) as u32],
+            client,
+        }
+    }
+
+    /// Index of the client in [`ClientIndex`].
+    pub(crate) fn client(self) -> u32 {
+        self.client
+    }
+
+    pub(crate) fn units(self) -> u64 {
+        u64::from(self.units[0]) | (u64::from(self.units[1]) << 32)
+    }
+}
+
+/// Client ids of stored transactions, so that packed states only need a 32-bit index instead of
+/// a (64-bit) client id.
+#[derive(Default)]
+pub(crate) struct ClientIndex(IndexSet<ClientId, FxBuildHasher>);
+
+impl ClientIndex {
+    /// Index of a client, added if it's new. Fails once there are 2^32 clients.
+    pub(crate) fn index(&mut self, client_id: ClientId) -> anyhow::Result<u32> {
+        let idx = self.0.get_index_of(&client_id).unwrap_or(self.0.len());
+        let index = u32::try_from(idx).context("too many clients with transactions (2^32)")?;
+        if idx == self.0.len() {
+            self.0.insert(client_id);
+        }
+        Ok(index)
+    }
+
+    /// Whether `count` more clients can be added.
+    pub(crate) fn has_room(&self, count: usize) -> bool {
+        u32::try_from(self.0.len().saturating_add(count)).is_ok()
+    }
+
+    pub(crate) fn client_id(&self, index: u32) -> ClientId {
+        let idx = usize::try_from(index).expect("u32 fits into usize");
+        *self.0.get_index(idx).expect("indexed client")
+    }
+
+    /// Approximate number of heap bytes used (entry with its hash, plus an index slot).
+    pub(crate) fn heap_size(&self) -> usize {
+        self.0
+            .capacity()
+            .saturating_mul(size_of::<(u64, ClientId)>().saturating_add(size_of::<usize>()))
+    }
+}
+
+/// Map keyed by transaction id. Ids which fit into 32 bits (the common case) are kept with 32-bit
+/// keys, so wider ids only cost memory once they're used.
+pub(crate) struct TxMap<V> {
+    narrow: FxHashMap<u32, V>,
+    wide: FxHashMap<u64, V>,
+}
+
+impl<V> Default for TxMap<V> {
+    fn default() -> Self {
+        Self {
+            narrow: FxHashMap::default(),
+            wide: FxHashMap::default(),
+        }
+    }
+}
+
+impl<V> TxMap<V> {
+    pub(crate) fn get(&self, transaction_id: TransactionId) -> Option<&V> {
+        match u32::try_from(transaction_id.0) {
+            Ok(id) => self.narrow.get(&id),
+            Err(_) => self.wide.get(&transaction_id.0),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, transaction_id: TransactionId) -> bool {
+        self.get(transaction_id).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, transaction_id: TransactionId, value: V) -> Option<V> {
+        match u32::try_from(transaction_id.0) {
+            Ok(id) => self.narrow.insert(id, value),
+            Err(_) => self.wide.insert(transaction_id.0, value),
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.narrow.reserve(additional);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.narrow.len().saturating_add(self.wide.len())
+    }
+
+    /// Approximate number of heap bytes used.
+    pub(crate) fn heap_size(&self) -> usize {
+        // note: hashbrown stores one control byte per bucket in addition to the entry.
+        let narrow = self
+            .narrow
+            .capacity()
+            .saturating_mul(size_of::<(u32, V)>().saturating_add(1));
+        let wide = self
+            .wide
+            .capacity()
+            .saturating_mul(size_of::<(u64, V)>().saturating_add(1));
+        narrow.saturating_add(wide)
+    }
+}
+
+/// Dense storage is abandoned once fewer than 1 in `DENSE_MIN_FILL` slots would be used...
+const DENSE_MIN_FILL: usize = 4;
+/// ...unless it's at most this long (so ids don't have to start at 0).
+const DENSE_MIN_LEN: usize = 1 << 16;
+
+/// Map from transaction id to packed state.
+///
+/// Starts as a `Vec` indexed by transaction id, which only costs 16 bytes per transaction when
+/// ids are (mostly) sequential. Once ids become sparse it switches to a hash map with a fast
+/// non-cryptographic hasher (20 bytes per entry + hash table overhead, 24 for ids wider than 32
+/// bits).
+pub(crate) enum TxStorage {
+    Dense {
+        states: Vec<PackedState>,
+        len: usize,
+    },
+    Sparse(TxMap<PackedState>),
+}
+
+impl Default for TxStorage {
+    fn default() -> Self {
+        Self::Dense {
+            states: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl TxStorage {
+    pub(crate) fn get(&self, transaction_id: TransactionId) -> Option<PackedState> {
+        match self {
+            Self::Dense { states, .. } => index(transaction_id)
+                .and_then(|idx| states.get(idx))
+                .copied()
+                .filter(|state| !state.is_vacant()),
+            Self::Sparse(states) => states.get(transaction_id).copied(),
+        }
+    }
+
+    /// Inserts or replaces transaction state.
+    pub(crate) fn set(&mut self, transaction_id: TransactionId, state: PackedState) {
+        debug_assert!(!state.is_vacant());
+        match self {
+            Self::Dense { states, len } => {
+                // note: The limit depends on the number of stored states, so that far apart ids
+                // can't make the `Vec` grow without bound.
+                let max_len = len
+                    .saturating_add(1)
+                    .saturating_mul(DENSE_MIN_FILL)
+                    .max(DENSE_MIN_LEN);
+                let Some(idx) = index(transaction_id).filter(|idx| *idx < max_len) else {
+                    self.make_sparse();
+                    return self.set(transaction_id, state);
+                };
+                if idx >= states.len() {
+                    states.resize(idx.saturating_add(1), PackedState::default());
+                }
+                if states[idx].is_vacant() {
+                    *len = len.saturating_add(1);
+                }
+                states[idx] = state;
+            }
+            Self::Sparse(states) => {
+                states.insert(transaction_id, state);
+            }
+        }
+    }
+
+    fn make_sparse(&mut self) {
+        let Self::Dense { states, len } = self else {
+            return;
+        };
+        let mut sparse = TxMap::default();
+        sparse.reserve(*len);
+        for (idx, state) in states.iter().enumerate() {
+            if !state.is_vacant() {
+                let id = u64::try_from(idx).expect("usize fits into u64");
+                sparse.insert(TransactionId(id), *state);
+            }
+        }
+        *self = Self::Sparse(sparse);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Dense { len, .. } => *len,
+            Self::Sparse(states) => states.len(),
+        }
+    }
+
+    /// Approximate number of heap bytes used.
+    pub(crate) fn heap_size(&self) -> usize {
+        match self {
+            Self::Dense { states, .. } => {
+                states.capacity().saturating_mul(size_of::<PackedState>())
+            }
+            Self::Sparse(states) => states.heap_size(),
+        }
+    }
+}
+
+fn index(transaction_id: TransactionId) -> Option<usize> {
+    usize::try_from(transaction_id.0).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dense_to_sparse() {
+        let mut storage = TxStorage::default();
+        let deposited = PackedState::new(1, 5, TransactionStatus::Deposited, None);
+        for id in 0..1000 {
+            storage.set(TransactionId(id), deposited);
+        }
+        assert!(matches!(storage, TxStorage::Dense { .. }));
+        assert_eq!(storage.len(), 1000);
+        assert!(storage.heap_size() <= 1000 * 2 * size_of::<PackedState>());
+
+        let disputed = PackedState::new(1, 5, TransactionStatus::Disputed, Some(1));
+        storage.set(TransactionId(10), disputed);
+        storage.set(TransactionId(u32::MAX.into()), disputed);
+        storage.set(TransactionId(u64::MAX), deposited);
+        assert!(matches!(storage, TxStorage::Sparse(_)));
+        assert_eq!(storage.len(), 1002);
+        assert_eq!(storage.get(TransactionId(10)), Some(disputed));
+        assert_eq!(storage.get(TransactionId(999)), Some(deposited));
+        assert_eq!(storage.get(TransactionId(u32::MAX.into())), Some(disputed));
+        assert_eq!(storage.get(TransactionId(u64::MAX)), Some(deposited));
+        assert_eq!(storage.get(TransactionId(1000)), None);
+    }
+
+    #[test]
+    fn test_sparse_ids() {
+        let mut storage = TxStorage::default();
+        let deposited = PackedState::new(1, 5, TransactionStatus::Deposited, None);
+        for shift in 0..37 {
+            storage.set(TransactionId(1 << shift), deposited);
+        }
+        assert!(matches!(storage, TxStorage::Sparse(_)));
+        assert_eq!(storage.len(), 37);
+        assert!(storage.heap_size() <= DENSE_MIN_LEN * size_of::<PackedState>());
+        assert_eq!(storage.get(TransactionId(1 << 36)), Some(deposited));
+        assert_eq!(storage.get(TransactionId(3)), None);
+    }
+
+    #[test]
+    fn test_packed_state() {
+        for status in [
+            TransactionStatus::Deposited,
+            TransactionStatus::Withdrawn,
+            TransactionStatus::Disputed,
+            TransactionStatus::Resolved,
+            TransactionStatus::Chargedback,
+            TransactionStatus::Pending,
+            TransactionStatus::Declined,
+            TransactionStatus::Authorized,
+            TransactionStatus::Captured,
+            TransactionStatus::Voided,
+            TransactionStatus::Reversed,
+        ] {
+            let state = PackedState::new(u32::MAX, PackedState::MAX_UNITS, status, Some(u32::MAX));
+            assert_eq!(state.client(), u32::MAX);
+            assert_eq!(state.timestamp(), Some(u32::MAX));
+            assert_eq!(state.units(), PackedState::MAX_UNITS);
+            assert_eq!(state.status(), status);
+        }
+
+        assert_eq!(size_of::<PackedWithdrawal>(), 12);
+        let withdrawal = PackedWithdrawal::new(u32::MAX, PackedState::MAX_UNITS);
+        assert_eq!(withdrawal.client(), u32::MAX);
+        assert_eq!(withdrawal.units(), PackedState::MAX_UNITS);
+    }
+}
@@ -1,166 +1,713 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::{collections::BTreeSet, time::Duration};
 
-use anyhow::{anyhow, ensure, Context};
+use anyhow::{bail, ensure, Context};
+use roaring::RoaringTreemap;
 use rust_decimal::Decimal;
 
-use crate::client::AuthorizedWithdrawal;
+use crate::{
+    client::ClientId,
+    event::Event,
+    storage::{ClientIndex, PackedState, PackedWithdrawal, TxMap, TxStorage},
+    DECIMAL_PLACES,
+};
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-pub(crate) struct TransactionId(pub(crate) u32);
+#[derive(
+    Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct TransactionId(pub u64);
 
 /// Current state of a transaction.
-#[derive(Debug)]
-pub(crate) enum TransactionStatus {
-    Deposited,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum TransactionStatus {
+    // note: Starts at 1, so that packed state is never 0 (see `PackedState`).
+    Deposited = 1,
     Withdrawn,
     Disputed,
     Resolved,
     Chargedback,
+    /// Withdrawal on hold, awaiting review.
+    Pending,
+    /// Held withdrawal which was declined.
+    Declined,
+    /// Funds reserved by an authorization, awaiting capture.
+    Authorized,
+    /// Authorization which was captured (fully or partially).
+    Captured,
+    /// Authorization which was voided.
+    Voided,
+    /// Chargeback which was reversed.
+    Reversed,
+}
+
+impl TransactionStatus {
+    pub(crate) fn from_repr(repr: u8) -> Option<Self> {
+        Some(match repr {
+            1 => Self::Deposited,
+            2 => Self::Withdrawn,
+            3 => Self::Disputed,
+            4 => Self::Resolved,
+            5 => Self::Chargedback,
+            6 => Self::Pending,
+            7 => Self::Declined,
+            8 => Self::Authorized,
+            9 => Self::Captured,
+            10 => Self::Voided,
+            11 => Self::Reversed,
+            _ => return None,
+        })
+    }
+
+    fn is_deposit(self) -> bool {
+        matches!(
+            self,
+            Self::Deposited | Self::Disputed | Self::Resolved | Self::Chargedback | Self::Reversed
+        )
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct TransactionState {
+    client_id: ClientId,
     amount: Decimal,
     status: TransactionStatus,
+    /// Time of the deposit, or of the dispute while it's disputed (or resolved/charged back).
+    timestamp: Option<u32>,
 }
 
-#[derive(Default)]
-pub(crate) struct TransactionDb {
-    transactions: HashMap<TransactionId, TransactionState>,
+/// Time limits for disputes. They're only enforced when both operations involved have
+/// timestamps.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DisputePolicy {
+    /// How long after a deposit it can still be disputed.
+    pub window: Option<Duration>,
+    /// How long a dispute can stay open, later resolve/chargeback is rejected.
+    pub max_duration: Option<Duration>,
+    /// What happens to disputes open for longer than `max_duration` once processing gets past
+    /// the deadline (see [`Engine::expire_next`](crate::Engine::expire_next)). By default they
+    /// stay open.
+    pub on_expiry: Option<DisputeExpiry>,
+    /// Unlock the account when a chargeback is reversed.
+    pub unlock_on_reversal: bool,
+}
+
+/// Outcome of an expired dispute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeExpiry {
+    Resolve,
+    Chargeback,
+}
+
+/// Time between two timestamps, `None` if either of them is unknown.
+fn elapsed(since: Option<u32>, until: Option<u32>) -> Option<Duration> {
+    let seconds = until?.saturating_sub(since?);
+    Some(Duration::from_secs(u64::from(seconds)))
+}
+
+/// Whether a deposit/withdrawal is new or an identical re-submission of an already recorded
+/// one (same transaction id, client and amount).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Submission {
+    New,
+    Repeated,
+}
+
+/// Transactions are stored compactly: amounts are kept as fixed-point integers at the
+/// configured scale (amounts are rounded to it when a transaction is recorded).
+///
+/// Only disputable transactions (deposits, transfers) are stored. Ids of all transactions are kept in a
+/// separate bitmap, so duplicates are still rejected, while a withdrawal only keeps its client
+/// (index) and amount, to recognize re-submissions.
+pub struct TransactionDb {
+    /// Ids of all recorded transactions, regardless of type.
+    ids: RoaringTreemap,
+    /// Disputable transactions.
+    transactions: TxStorage,
+    /// Client & amount of withdrawals, to recognize re-submissions.
+    withdrawals: TxMap<PackedWithdrawal>,
+    /// Source clients (indexes) of transfers (stored like deposits into the destination
+    /// account).
+    transfer_sources: TxMap<u32>,
+    /// Clients of stored transactions.
+    clients: ClientIndex,
+    scale: u32,
+    dispute_policy: DisputePolicy,
+    /// Open disputes with known opening time, by time.
+    open_disputes: BTreeSet<(u32, TransactionId)>,
+}
+
+impl Default for TransactionDb {
+    fn default() -> Self {
+        Self::with_scale(DECIMAL_PLACES)
+    }
 }
 
 impl TransactionDb {
-    pub(crate) fn deposit(
-        &mut self,
+    /// Largest supported scale (number of decimal places).
+    pub const MAX_SCALE: u32 = 12;
+
+    /// Panics if `scale` is greater than [`Self::MAX_SCALE`].
+    pub fn with_scale(scale: u32) -> Self {
+        assert!(
+            scale <= Self::MAX_SCALE,
+            "scale should be <= {}",
+            Self::MAX_SCALE
+        );
+        Self {
+            ids: RoaringTreemap::new(),
+            transactions: TxStorage::default(),
+            withdrawals: TxMap::default(),
+            transfer_sources: TxMap::default(),
+            clients: ClientIndex::default(),
+            scale,
+            dispute_policy: DisputePolicy::default(),
+            open_disputes: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    pub fn dispute_policy(&self) -> &DisputePolicy {
+        &self.dispute_policy
+    }
+
+    /// Number of recorded transactions (of all types).
+    pub fn len(&self) -> u64 {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Ids of all recorded transactions.
+    pub(crate) fn ids(&self) -> &RoaringTreemap {
+        &self.ids
+    }
+
+    /// Number of stored (disputable) transactions.
+    pub fn disputable_len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Approximate number of bytes used to store transactions.
+    pub fn heap_size(&self) -> usize {
+        self.transactions
+            .heap_size()
+            .saturating_add(self.ids.serialized_size())
+            .saturating_add(self.withdrawals.heap_size())
+            .saturating_add(self.transfer_sources.heap_size())
+            .saturating_add(self.clients.heap_size())
+    }
+
+    fn client_of(&self, state: PackedState) -> ClientId {
+        self.clients.client_id(state.client())
+    }
+
+    /// Rounds `amount` to the configured scale, returns it in fixed-point units and as a
+    /// (normalized) decimal.
+    fn to_units(&self, amount: Decimal) -> anyhow::Result<(u64, Decimal)> {
+        let mut rounded = amount.round_dp(self.scale);
+        rounded.rescale(self.scale);
+        let units = u64::try_from(rounded.mantissa())
+            .ok()
+            .filter(|units| *units <= PackedState::MAX_UNITS && rounded.scale() == self.scale)
+            .context("amount is too large")?;
+        Ok((units, rounded.normalize()))
+    }
+
+    /// Fixed-point units of an amount which is recorded without rounding, to compare a
+    /// re-submission with the recorded transaction (`None` never matches).
+    fn exact_units(&self, amount: Decimal) -> Option<u64> {
+        let (units, rounded) = self.to_units(amount).ok()?;
+        (rounded == amount).then_some(units)
+    }
+
+    /// Checks that a deposit can be recorded with `transaction_id`, or that it has already been
+    /// recorded with the same client and amount.
+    pub(crate) fn check_deposit(
+        &self,
+        client_id: ClientId,
         transaction_id: TransactionId,
         amount: Decimal,
-    ) -> anyhow::Result<PersistedTx<Deposit>> {
-        ensure!(amount > Decimal::ZERO, "deposit amount must be > 0");
-
-        match self.transactions.entry(transaction_id) {
-            Entry::Occupied(_) => Err(anyhow!("transaction already exists")),
-            Entry::Vacant(entry) => {
-                entry.insert_entry(TransactionState {
-                    amount,
-                    status: TransactionStatus::Deposited,
-                });
-                Ok(PersistedTx {
-                    transaction_id,
-                    amount,
-                    state: Deposit,
-                })
-            }
+    ) -> anyhow::Result<Submission> {
+        if !self.ids.contains(transaction_id.0) {
+            return Ok(Submission::New);
         }
+        let existing = self.transactions.get(transaction_id);
+        let units = self.exact_units(amount);
+        ensure!(
+            existing.is_some_and(|state| state.status().is_deposit()
+                && self.client_of(state) == client_id
+                && Some(state.units()) == units)
+                && !self.transfer_sources.contains_key(transaction_id),
+            "transaction already exists"
+        );
+        Ok(Submission::Repeated)
     }
 
-    pub(crate) fn withdraw(
-        &mut self,
-        withdrawal: AuthorizedWithdrawal,
-    ) -> anyhow::Result<PersistedTx<Withdrawal>> {
-        match self.transactions.entry(withdrawal.transaction_id()) {
-            Entry::Occupied(_) => Err(anyhow!("transaction already exists")),
-            Entry::Vacant(entry) => {
-                entry.insert_entry(TransactionState {
-                    amount: *withdrawal.amount(),
-                    status: TransactionStatus::Withdrawn,
-                });
-                Ok(PersistedTx {
-                    transaction_id: withdrawal.transaction_id(),
-                    amount: *withdrawal.amount(),
-                    state: Withdrawal,
-                })
-            }
+    /// Checks that a transfer can be recorded with `transaction_id`, or that it has already
+    /// been recorded with the same clients and amount.
+    pub(crate) fn check_transfer(
+        &self,
+        from: ClientId,
+        to: ClientId,
+        transaction_id: TransactionId,
+        amount: Decimal,
+    ) -> anyhow::Result<Submission> {
+        if !self.ids.contains(transaction_id.0) {
+            return Ok(Submission::New);
         }
+        let existing = self.transactions.get(transaction_id);
+        let units = self.exact_units(amount);
+        ensure!(
+            existing
+                .is_some_and(|state| self.client_of(state) == to && Some(state.units()) == units)
+                && self.transfer_parties(transaction_id).map(|(from, _)| from) == Some(from),
+            "transaction already exists"
+        );
+        Ok(Submission::Repeated)
     }
 
-    /// Returns disputed amount.
-    pub(crate) fn dispute(
-        &mut self,
+    /// Client of a stored (disputable) transaction, the destination client of a transfer.
+    pub(crate) fn client(&self, transaction_id: TransactionId) -> Option<ClientId> {
+        Some(self.client_of(self.transactions.get(transaction_id)?))
+    }
+
+    /// Source and destination clients of a transfer, `None` if the transaction isn't one.
+    pub(crate) fn transfer_parties(
+        &self,
         transaction_id: TransactionId,
-    ) -> anyhow::Result<PersistedTx<Dispute>> {
-        let state = self
-            .transactions
-            .get_mut(&transaction_id)
-            .context("transaction does not exist")?;
+    ) -> Option<(ClientId, ClientId)> {
+        let from = self
+            .clients
+            .client_id(*self.transfer_sources.get(transaction_id)?);
+        let to = self.client_of(self.transactions.get(transaction_id)?);
+        Some((from, to))
+    }
 
-        // note: If we want to be able to dispute the same transaction after it's been resolved, then
-        // need to match against `TransactionStatus::Resolved` too.
+    /// Checks that a withdrawal can be recorded with `transaction_id`, or that it has already
+    /// been recorded with the same client and amount.
+    pub(crate) fn check_withdrawal(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Decimal,
+    ) -> anyhow::Result<Submission> {
+        if !self.ids.contains(transaction_id.0) {
+            return Ok(Submission::New);
+        }
+        let units = self.exact_units(amount);
+        // note: Withdrawals which were put on hold are stored.
+        if let Some(state) = self.transactions.get(transaction_id) {
+            let is_withdrawal = matches!(
+                state.status(),
+                TransactionStatus::Pending
+                    | TransactionStatus::Withdrawn
+                    | TransactionStatus::Declined
+            );
+            ensure!(
+                is_withdrawal && self.client_of(state) == client_id && Some(state.units()) == units,
+                "transaction already exists"
+            );
+            return Ok(Submission::Repeated);
+        }
+        let existing = self.withdrawals.get(transaction_id);
         ensure!(
-            matches!(state.status, TransactionStatus::Deposited),
-            "transaction ({:?}) can't be disputed",
-            state.status
+            existing.is_some_and(|withdrawal| self.clients.client_id(withdrawal.client())
+                == client_id
+                && Some(withdrawal.units()) == units),
+            "transaction already exists"
         );
-        state.status = TransactionStatus::Disputed;
-        Ok(PersistedTx {
-            transaction_id,
-            amount: state.amount,
-            state: Dispute,
+        Ok(Submission::Repeated)
+    }
+
+    /// Checks that an authorization can be recorded with `transaction_id`, or that it has
+    /// already been recorded with the same client and amount.
+    pub(crate) fn check_authorization(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Decimal,
+    ) -> anyhow::Result<Submission> {
+        if !self.ids.contains(transaction_id.0) {
+            return Ok(Submission::New);
+        }
+        let existing = self.transactions.get(transaction_id);
+        let units = self.exact_units(amount);
+        ensure!(
+            existing.is_some_and(|state| matches!(
+                state.status(),
+                TransactionStatus::Authorized
+                    | TransactionStatus::Captured
+                    | TransactionStatus::Voided
+            ) && self.client_of(state) == client_id
+                && Some(state.units()) == units),
+            "transaction already exists"
+        );
+        Ok(Submission::Repeated)
+    }
+
+    /// Current status of a transaction, `None` if it doesn't exist.
+    pub fn status(&self, transaction_id: TransactionId) -> Option<TransactionStatus> {
+        if let Some(state) = self.transactions.get(transaction_id) {
+            return Some(state.status());
+        }
+        self.withdrawals
+            .contains_key(transaction_id)
+            .then_some(TransactionStatus::Withdrawn)
+    }
+
+    fn get(&self, transaction_id: TransactionId) -> anyhow::Result<TransactionState> {
+        let Some(state) = self.transactions.get(transaction_id) else {
+            ensure!(
+                self.ids.contains(transaction_id.0),
+                "transaction does not exist"
+            );
+            // note: Only withdrawals aren't stored.
+            bail!(
+                "transaction ({:?}) can't be disputed",
+                TransactionStatus::Withdrawn
+            );
+        };
+        let units = i64::try_from(state.units()).expect("60 bit amount");
+        Ok(TransactionState {
+            client_id: self.client_of(state),
+            amount: Decimal::new(units, self.scale).normalize(),
+            status: state.status(),
+            timestamp: state.timestamp(),
         })
     }
 
-    /// Returns resolved amount.
-    pub(crate) fn resolve(
+    fn set(
         &mut self,
         transaction_id: TransactionId,
-    ) -> anyhow::Result<PersistedTx<Resolve>> {
-        let state = self
-            .transactions
-            .get_mut(&transaction_id)
+        state: &TransactionState,
+    ) -> anyhow::Result<()> {
+        let (units, _) = self
+            .to_units(state.amount)
+            .expect("stored amounts are always representable");
+        let client = self.clients.index(state.client_id)?;
+        self.transactions.set(
+            transaction_id,
+            PackedState::new(client, units, state.status, state.timestamp),
+        );
+        Ok(())
+    }
+
+    /// Rounds `amount` to the configured scale (as it would be recorded).
+    pub(crate) fn round_amount(&self, amount: Decimal) -> anyhow::Result<Decimal> {
+        self.to_units(amount).map(|(_, amount)| amount)
+    }
+
+    /// Checks that transaction has `expected` status, returns its current state.
+    fn check_status(
+        &self,
+        transaction_id: TransactionId,
+        expected: TransactionStatus,
+    ) -> anyhow::Result<TransactionState> {
+        let state = self.get(transaction_id)?;
+        if state.status != expected {
+            match expected {
+                TransactionStatus::Deposited => {
+                    bail!("transaction ({:?}) can't be disputed", state.status)
+                }
+                TransactionStatus::Chargedback => {
+                    bail!("transaction ({:?}) isn't charged back", state.status)
+                }
+                _ => bail!("transaction ({:?}) isn't under dispute", state.status),
+            }
+        }
+        Ok(state)
+    }
+
+    /// Checks that a dispute opened at `timestamp` is still within the dispute window, returns
+    /// disputed amount.
+    pub(crate) fn check_dispute(
+        &self,
+        transaction_id: TransactionId,
+        timestamp: Option<u32>,
+    ) -> anyhow::Result<Decimal> {
+        // note: If we want to be able to dispute the same transaction after it's been resolved, then
+        // need to allow `TransactionStatus::Resolved` too.
+        let state = self.check_status(transaction_id, TransactionStatus::Deposited)?;
+        if let (Some(window), Some(elapsed)) = (
+            self.dispute_policy.window,
+            elapsed(state.timestamp, timestamp),
+        ) {
+            ensure!(elapsed <= window, "dispute window expired");
+        }
+        Ok(state.amount)
+    }
+
+    /// Checks that dispute is still open at `timestamp` (hasn't exceeded maximum duration).
+    fn check_open_dispute(
+        &self,
+        transaction_id: TransactionId,
+        timestamp: Option<u32>,
+    ) -> anyhow::Result<TransactionState> {
+        let state = self.check_status(transaction_id, TransactionStatus::Disputed)?;
+        if let (Some(max_duration), Some(elapsed)) = (
+            self.dispute_policy.max_duration,
+            elapsed(state.timestamp, timestamp),
+        ) {
+            ensure!(elapsed <= max_duration, "maximum dispute duration exceeded");
+        }
+        Ok(state)
+    }
+
+    /// Returns the oldest open dispute which exceeded maximum duration before `now`: client,
+    /// transaction and the deadline.
+    pub(crate) fn expired_dispute(&self, now: u32) -> Option<(ClientId, TransactionId, u32)> {
+        let max_duration = self.dispute_policy.max_duration?;
+        let &(opened_at, transaction_id) = self.open_disputes.first()?;
+        // note: Deadline that doesn't fit into a timestamp is never reached.
+        let deadline = u64::from(opened_at).saturating_add(max_duration.as_secs());
+        let deadline = u32::try_from(deadline)
+            .ok()
+            .filter(|deadline| *deadline < now)?;
+        let state = self.transactions.get(transaction_id)?;
+        Some((self.client_of(state), transaction_id, deadline))
+    }
+
+    /// Checks that a held withdrawal/authorization has `expected` status, returns its current
+    /// state.
+    fn check_reservation(
+        &self,
+        transaction_id: TransactionId,
+        expected: TransactionStatus,
+    ) -> anyhow::Result<TransactionState> {
+        let status = self
+            .status(transaction_id)
             .context("transaction does not exist")?;
+        if status != expected {
+            match expected {
+                TransactionStatus::Pending => {
+                    bail!("transaction ({status:?}) isn't pending review")
+                }
+                _ => bail!("transaction ({status:?}) isn't authorized"),
+            }
+        }
+        self.get(transaction_id)
+    }
 
+    /// Checks that a held withdrawal of the client awaits review, returns its amount.
+    pub(crate) fn check_review(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> anyhow::Result<Decimal> {
+        let state = self.check_reservation(transaction_id, TransactionStatus::Pending)?;
         ensure!(
-            matches!(state.status, TransactionStatus::Disputed),
-            "transaction ({:?}) isn't under dispute",
-            state.status
+            state.client_id == client_id,
+            "transaction belongs to another client"
         );
-        state.status = TransactionStatus::Resolved;
-        Ok(PersistedTx {
-            transaction_id,
-            amount: state.amount,
-            state: Resolve,
-        })
+        Ok(state.amount)
+    }
+
+    /// Checks that an authorization of the client awaits capture, returns authorized amount.
+    pub(crate) fn check_authorized(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> anyhow::Result<Decimal> {
+        let state = self.check_reservation(transaction_id, TransactionStatus::Authorized)?;
+        ensure!(
+            state.client_id == client_id,
+            "transaction belongs to another client"
+        );
+        Ok(state.amount)
+    }
+
+    /// Returns resolved amount.
+    pub(crate) fn check_resolve(
+        &self,
+        transaction_id: TransactionId,
+        timestamp: Option<u32>,
+    ) -> anyhow::Result<Decimal> {
+        let state = self.check_open_dispute(transaction_id, timestamp)?;
+        Ok(state.amount)
     }
 
     /// Returns amount charged back.
-    pub(crate) fn chargeback(
+    pub(crate) fn check_chargeback(
+        &self,
+        transaction_id: TransactionId,
+        timestamp: Option<u32>,
+    ) -> anyhow::Result<Decimal> {
+        let state = self.check_open_dispute(transaction_id, timestamp)?;
+        Ok(state.amount)
+    }
+
+    /// Returns amount of the chargeback to reverse.
+    pub(crate) fn check_reversal(&self, transaction_id: TransactionId) -> anyhow::Result<Decimal> {
+        let state = self.check_status(transaction_id, TransactionStatus::Chargedback)?;
+        Ok(state.amount)
+    }
+
+    /// Completes a held withdrawal/authorization with `to` status.
+    fn complete_reservation(
         &mut self,
         transaction_id: TransactionId,
-    ) -> anyhow::Result<PersistedTx<Chargeback>> {
-        let state = self
-            .transactions
-            .get_mut(&transaction_id)
-            .context("transaction does not exist")?;
+        from: TransactionStatus,
+        to: TransactionStatus,
+    ) -> anyhow::Result<()> {
+        let mut state = self.check_reservation(transaction_id, from)?;
+        state.status = to;
+        self.set(transaction_id, &state)?;
+        Ok(())
+    }
 
+    /// Closes a dispute with `to` status.
+    fn close_dispute(
+        &mut self,
+        transaction_id: TransactionId,
+        to: TransactionStatus,
+    ) -> anyhow::Result<()> {
+        let mut state = self.check_status(transaction_id, TransactionStatus::Disputed)?;
+        state.status = to;
+        self.set(transaction_id, &state)?;
+        if let Some(opened_at) = state.timestamp {
+            self.open_disputes.remove(&(opened_at, transaction_id));
+        }
+        Ok(())
+    }
+
+    /// Fails if clients of a new transaction might not fit into the client index (2^32 clients).
+    pub(crate) fn check_capacity(&self) -> anyhow::Result<()> {
         ensure!(
-            matches!(state.status, TransactionStatus::Disputed),
-            "transaction ({:?}) isn't under dispute",
-            state.status
+            self.clients.has_room(2),
+            "too many clients with transactions (2^32)"
         );
-        state.status = TransactionStatus::Chargedback;
-        Ok(PersistedTx {
-            transaction_id,
-            amount: state.amount,
-            state: Chargeback,
-        })
+        Ok(())
     }
-}
 
-/// note: each state type could have additional fields specific to that state.
-#[allow(dead_code)]
-pub(crate) struct PersistedTx<S> {
-    transaction_id: TransactionId,
-    amount: Decimal,
-    state: S,
-}
-
-impl<S> PersistedTx<S> {
-    pub(crate) fn amount(&self) -> Decimal {
-        self.amount
+    /// Updates transactions with an event. Events are validated when they're created, checks
+    /// here only guard against inconsistent event logs.
+    pub(crate) fn apply(&mut self, event: &Event) -> anyhow::Result<()> {
+        match *event {
+            Event::Deposited {
+                client,
+                tx,
+                amount,
+                timestamp,
+            } => {
+                ensure!(!self.ids.contains(tx.0), "transaction already exists");
+                let (units, _) = self.to_units(amount)?;
+                let client = self.clients.index(client)?;
+                self.ids.insert(tx.0);
+                self.transactions.set(
+                    tx,
+                    PackedState::new(client, units, TransactionStatus::Deposited, timestamp),
+                );
+            }
+            Event::Withdrawn { client, tx, amount } => {
+                ensure!(!self.ids.contains(tx.0), "transaction already exists");
+                let (units, _) = self.to_units(amount)?;
+                // note: Withdrawals can't be disputed, so only the id, client and amount are
+                // recorded.
+                let withdrawal = PackedWithdrawal::new(self.clients.index(client)?, units);
+                self.ids.insert(tx.0);
+                self.withdrawals.insert(tx, withdrawal);
+            }
+            Event::DisputeOpened { tx, timestamp, .. } => {
+                let mut state = self.check_status(tx, TransactionStatus::Deposited)?;
+                state.status = TransactionStatus::Disputed;
+                state.timestamp = timestamp;
+                self.set(tx, &state)?;
+                if let Some(opened_at) = timestamp {
+                    self.open_disputes.insert((opened_at, tx));
+                }
+            }
+            Event::DisputeResolved { tx, .. } => {
+                self.close_dispute(tx, TransactionStatus::Resolved)?;
+            }
+            Event::ChargedBack { tx, .. } => {
+                self.close_dispute(tx, TransactionStatus::Chargedback)?;
+            }
+            Event::AccountOpened { .. }
+            | Event::AccountLocked { .. }
+            | Event::AccountUnlocked { .. }
+            | Event::AccountClosed { .. }
+            | Event::FeeCharged { .. }
+            | Event::TransactionScoped { .. } => {}
+            Event::ChargebackReversed { tx, .. } => {
+                let mut state = self.check_status(tx, TransactionStatus::Chargedback)?;
+                state.status = TransactionStatus::Reversed;
+                self.set(tx, &state)?;
+            }
+            Event::Transferred {
+                client,
+                to,
+                tx,
+                amount,
+                timestamp,
+            } => {
+                ensure!(!self.ids.contains(tx.0), "transaction already exists");
+                let (units, _) = self.to_units(amount)?;
+                let from = self.clients.index(client)?;
+                let to = self.clients.index(to)?;
+                self.ids.insert(tx.0);
+                self.transactions.set(
+                    tx,
+                    PackedState::new(to, units, TransactionStatus::Deposited, timestamp),
+                );
+                self.transfer_sources.insert(tx, from);
+            }
+            Event::WithdrawalHeld { client, tx, amount } => {
+                ensure!(!self.ids.contains(tx.0), "transaction already exists");
+                let (units, _) = self.to_units(amount)?;
+                let client = self.clients.index(client)?;
+                self.ids.insert(tx.0);
+                self.transactions.set(
+                    tx,
+                    PackedState::new(client, units, TransactionStatus::Pending, None),
+                );
+            }
+            Event::WithdrawalApproved { tx, .. } => {
+                self.complete_reservation(
+                    tx,
+                    TransactionStatus::Pending,
+                    TransactionStatus::Withdrawn,
+                )?;
+            }
+            Event::WithdrawalDeclined { tx, .. } => {
+                self.complete_reservation(
+                    tx,
+                    TransactionStatus::Pending,
+                    TransactionStatus::Declined,
+                )?;
+            }
+            Event::Authorized { client, tx, amount } => {
+                ensure!(!self.ids.contains(tx.0), "transaction already exists");
+                let (units, _) = self.to_units(amount)?;
+                let client = self.clients.index(client)?;
+                self.ids.insert(tx.0);
+                self.transactions.set(
+                    tx,
+                    PackedState::new(client, units, TransactionStatus::Authorized, None),
+                );
+            }
+            Event::Captured { tx, .. } => {
+                // note: Authorized amount is kept, to recognize re-submitted authorizations.
+                self.complete_reservation(
+                    tx,
+                    TransactionStatus::Authorized,
+                    TransactionStatus::Captured,
+                )?;
+            }
+            Event::Voided { tx, .. } => {
+                self.complete_reservation(
+                    tx,
+                    TransactionStatus::Authorized,
+                    TransactionStatus::Voided,
+                )?;
+            }
+        }
+        Ok(())
     }
 }
-
-pub(crate) struct Deposit;
-pub(crate) struct Withdrawal;
-pub(crate) struct Dispute;
-pub(crate) struct Resolve;
-pub(crate) struct Chargeback;